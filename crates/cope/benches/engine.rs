@@ -0,0 +1,72 @@
+//! Times creating, updating and clearing a large number of atoms, each with a
+//! reaction subscribed to it.
+//!
+//! Run with `cargo bench -p cope`.
+
+#![warn(future_incompatible, rust_2018_compatibility, rust_2018_idioms, unused)]
+#![warn(clippy::pedantic)]
+#![cfg_attr(feature = "strict", deny(warnings))]
+
+use cope::instance::{Atom, Engine};
+use std::{
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+const ATOMS: usize = 10_000;
+const ROUNDS: usize = 50;
+
+fn main() {
+    let mut create = Vec::with_capacity(ROUNDS);
+    let mut update = Vec::with_capacity(ROUNDS);
+    let mut clear = Vec::with_capacity(ROUNDS);
+
+    for _ in 0..ROUNDS {
+        let engine = Rc::new(Engine::new());
+
+        let start = Instant::now();
+        let (scope, atoms) = engine.create_root(|| {
+            (0..ATOMS)
+                .map(|i| {
+                    let atom = Atom::new(engine.clone(), i);
+                    engine.react({
+                        let atom = atom.clone();
+                        move || drop(atom.get())
+                    });
+                    atom
+                })
+                .collect::<Vec<_>>()
+        });
+        create.push(start.elapsed());
+
+        let start = Instant::now();
+        {
+            let _batch = engine.batch();
+            for atom in &atoms {
+                let value = *atom.sample();
+                atom.set(value + 1);
+            }
+        }
+        update.push(start.elapsed());
+
+        let start = Instant::now();
+        scope.dispose();
+        drop(atoms);
+        clear.push(start.elapsed());
+    }
+
+    println!("{} atoms, {} rounds", ATOMS, ROUNDS);
+    report("create", create);
+    report("update", update);
+    report("clear", clear);
+}
+
+fn report(name: &str, mut times: Vec<Duration>) {
+    times.sort();
+    println!(
+        "{:>8}: median {:>9.3?}, min {:>9.3?}",
+        name,
+        times[times.len() / 2],
+        times[0],
+    );
+}
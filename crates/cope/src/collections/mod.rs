@@ -0,0 +1,6 @@
+//! Collections that track changes to their contents, not just to the whole
+//! collection.
+
+pub use self::reactive_vec::{ListMutation, ReactiveVec, Subscription};
+
+mod reactive_vec;
@@ -0,0 +1,701 @@
+use crate::instance::{Atom, AtomMut, Engine};
+use std::{
+    cell::{Cell, Ref, RefCell},
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
+    mem,
+    ops::{Bound, Range, RangeBounds},
+    rc::Rc,
+};
+
+/// One change to a [`ReactiveVec`], as seen by a [`Subscription`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ListMutation {
+    /// An item was inserted at this index.
+    Insert(usize),
+    /// The item at this index was removed.
+    Remove(usize),
+}
+
+/// A `Vec` that records how it changes, so views of it can be patched instead
+/// of rebuilt.
+///
+/// Reading the vec subscribes the current reaction to it, like an atom.
+/// Everything that changes it also appends to a log of [`ListMutation`]s, which
+/// any number of [`Subscription`]s can read at their own pace.
+///
+/// Clones share the same items.
+pub struct ReactiveVec<T> {
+    items: Atom<Vec<T>>,
+    log: Rc<RefCell<Log>>,
+}
+
+impl<T: 'static> ReactiveVec<T> {
+    pub fn new(engine: Rc<Engine>) -> Self {
+        Self::from_vec(engine, Vec::new())
+    }
+
+    pub fn from_vec(engine: Rc<Engine>, items: Vec<T>) -> Self {
+        Self {
+            items: Atom::new(engine, items),
+            log: Rc::new(RefCell::new(Log::default())),
+        }
+    }
+
+    /// Starts a log of the changes made from now on. The first batch of changes
+    /// taken from it also inserts every item that's already here.
+    #[must_use]
+    pub fn subscribe(&self) -> Subscription<T> {
+        Subscription {
+            vec: self.clone(),
+            id: self.log.borrow_mut().subscribe(),
+            existing: Cell::new(self.items.sample().len()),
+        }
+    }
+
+    /// Reads the items, and subscribes the current reaction to them.
+    #[must_use]
+    pub fn as_slice(&self) -> Ref<'_, [T]> {
+        Ref::map(self.items.get(), Vec::as_slice)
+    }
+
+    /// Reads the items without subscribing the current reaction to them.
+    #[must_use]
+    pub fn sample(&self) -> Ref<'_, [T]> {
+        Ref::map(self.items.sample(), Vec::as_slice)
+    }
+
+    #[must_use]
+    pub fn get(&self, index: usize) -> Option<Ref<'_, T>> {
+        let items = self.items.get();
+        if index >= items.len() {
+            return None;
+        }
+        Some(Ref::map(items, |items| &items[index]))
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.items.get().len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.items.get().is_empty()
+    }
+
+    /// Reserves capacity without notifying anyone, since nothing visible
+    /// changes.
+    pub fn reserve(&self, additional: usize) {
+        self.items.sample_mut().reserve(additional);
+    }
+
+    pub fn push(&self, value: T) {
+        self.mutate(|items, log| {
+            log.push(ListMutation::Insert(items.len()));
+            items.push(value);
+        });
+    }
+
+    #[allow(clippy::must_use_candidate)]
+    pub fn pop(&self) -> Option<T> {
+        self.mutate(|items, log| {
+            let value = items.pop()?;
+            log.push(ListMutation::Remove(items.len()));
+            Some(value)
+        })
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `index` is greater than the length.
+    pub fn insert(&self, index: usize, value: T) {
+        self.mutate(|items, log| {
+            items.insert(index, value);
+            log.push(ListMutation::Insert(index));
+        });
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    #[allow(clippy::must_use_candidate)]
+    pub fn remove(&self, index: usize) -> T {
+        self.mutate(|items, log| {
+            let value = items.remove(index);
+            log.push(ListMutation::Remove(index));
+            value
+        })
+    }
+
+    /// Replaces the item at `index`, and returns the old one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn set(&self, index: usize, value: T) -> T {
+        self.mutate(|items, log| {
+            let old = mem::replace(&mut items[index], value);
+            log.push(ListMutation::Remove(index));
+            log.push(ListMutation::Insert(index));
+            old
+        })
+    }
+
+    /// Moves the item at `from` so it ends up at `to`, shifting the items in
+    /// between.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either index is out of bounds.
+    pub fn move_item(&self, from: usize, to: usize) {
+        self.mutate(|items, log| {
+            assert!(to < items.len(), "move destination out of bounds");
+            if from == to {
+                return;
+            }
+            let value = items.remove(from);
+            items.insert(to, value);
+            log.push(ListMutation::Remove(from));
+            log.push(ListMutation::Insert(to));
+        });
+    }
+
+    /// # Panics
+    ///
+    /// Panics if either index is out of bounds.
+    pub fn swap(&self, a: usize, b: usize) {
+        self.mutate(|items, log| {
+            items.swap(a, b);
+            if a == b {
+                return;
+            }
+            log.push(ListMutation::Remove(a));
+            log.push(ListMutation::Insert(a));
+            log.push(ListMutation::Remove(b));
+            log.push(ListMutation::Insert(b));
+        });
+    }
+
+    pub fn truncate(&self, len: usize) {
+        self.mutate(|items, log| {
+            log.extend((len..items.len()).rev().map(ListMutation::Remove));
+            items.truncate(len);
+        });
+    }
+
+    pub fn clear(&self) {
+        self.truncate(0);
+    }
+
+    /// Keeps only the items `f` returns true for. `f` is free to read the vec.
+    pub fn retain(&self, f: impl FnMut(&T) -> bool) {
+        // Decide before borrowing the items mutably.
+        let keep = self.sample().iter().map(f).collect::<Vec<_>>();
+        self.mutate(|items, log| {
+            let mut keep = keep.into_iter();
+            let mut removed = Vec::new();
+            let mut index = 0;
+            items.retain(|_| {
+                let keep = keep.next().unwrap();
+                if !keep {
+                    removed.push(index);
+                }
+                index += 1;
+                keep
+            });
+            // Back to front, so each index is still valid when it's applied.
+            log.extend(removed.into_iter().rev().map(ListMutation::Remove));
+        });
+    }
+
+    /// Sorts the items, which is stable like [`slice::sort_by`]. The items
+    /// from the first one that moved through the last one that moved are
+    /// logged as removed and inserted again.
+    ///
+    /// `compare` is free to read the vec. If it panics, the vec is left as it
+    /// was.
+    pub fn sort_by(&self, mut compare: impl FnMut(&T, &T) -> Ordering) {
+        // Sort the indices rather than the items, so nothing has moved yet if
+        // `compare` panics.
+        let order = {
+            let items = self.sample();
+            let mut order = (0..items.len()).collect::<Vec<_>>();
+            order.sort_by(|&a, &b| compare(&items[a], &items[b]));
+            order
+        };
+        self.mutate(|items, log| {
+            let moved =
+                order
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(new, &old)| if new == old { None } else { Some(new) });
+            let first = moved.clone().next();
+            let last = moved.last();
+            let mut slots = mem::take(items).into_iter().map(Some).collect::<Vec<_>>();
+            items.extend(order.iter().map(|&old| slots[old].take().unwrap()));
+
+            if let (Some(first), Some(last)) = (first, last) {
+                log.extend((first..=last).rev().map(ListMutation::Remove));
+                log.extend((first..=last).map(ListMutation::Insert));
+            }
+        });
+    }
+
+    /// Replaces the items in `range` with `replace_with`, and returns the
+    /// items that were removed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn splice(
+        &self,
+        range: impl RangeBounds<usize>,
+        replace_with: impl IntoIterator<Item = T>,
+    ) -> Vec<T> {
+        // Run the iterator before borrowing the items mutably, in case it reads
+        // the vec.
+        let replace_with = replace_with.into_iter().collect::<Vec<_>>();
+        self.mutate(|items, log| {
+            let range = resolve(&range, items.len());
+            let old_len = items.len();
+            let removed = items
+                .splice(range.clone(), replace_with)
+                .collect::<Vec<_>>();
+            let inserted = items.len() + removed.len() - old_len;
+            log.extend(range.clone().rev().map(ListMutation::Remove));
+            log.extend((range.start..range.start + inserted).map(ListMutation::Insert));
+            removed
+        })
+    }
+
+    /// Removes the items in `range`, and returns them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn drain(&self, range: impl RangeBounds<usize>) -> Vec<T> {
+        self.splice(range, None)
+    }
+
+    pub fn extend(&self, iter: impl IntoIterator<Item = T>) {
+        // Run the iterator before borrowing the items mutably, in case it reads
+        // the vec.
+        let iter = iter.into_iter().collect::<Vec<_>>();
+        self.mutate(|items, log| {
+            let old_len = items.len();
+            items.extend(iter);
+            log.extend((old_len..items.len()).map(ListMutation::Insert));
+        });
+    }
+
+    /// Runs `f` on the items, then logs the mutations it returns. Subscribers
+    /// are only notified if there was at least one.
+    ///
+    /// The items stay borrowed while `f` runs, so it must not call back into
+    /// user code.
+    ///
+    /// If `f` panics, say on an index that's out of bounds, nothing is logged
+    /// or notified, since the guard doesn't notify while unwinding.
+    fn mutate<R>(&self, f: impl FnOnce(&mut Vec<T>, &mut Vec<ListMutation>) -> R) -> R {
+        let mut items = self.items.get_mut();
+        let mut mutations = Vec::new();
+        let result = f(&mut items, &mut mutations);
+        if mutations.is_empty() {
+            AtomMut::mark_unchanged(&mut items);
+        }
+        // Log before the guard is dropped, so reactions that run then can see
+        // what changed.
+        self.log.borrow_mut().record(mutations);
+        drop(items);
+        result
+    }
+}
+
+impl<T> Clone for ReactiveVec<T> {
+    fn clone(&self) -> Self {
+        Self {
+            items: self.items.clone(),
+            log: self.log.clone(),
+        }
+    }
+}
+
+/// A log of the changes made to a [`ReactiveVec`], independent of any other
+/// subscription to the same vec.
+pub struct Subscription<T> {
+    vec: ReactiveVec<T>,
+    id: u64,
+    /// How many items were in the vec when the subscription started, or zero
+    /// once they've been taken.
+    existing: Cell<usize>,
+}
+
+impl<T: 'static> Subscription<T> {
+    /// Takes the changes made since the last call, and subscribes the current
+    /// reaction to the vec.
+    ///
+    /// Each index refers to the vec as it was right after the changes before
+    /// it. To keep a view in sync, apply the changes in order to the view's
+    /// copy of the list, inserting a placeholder for each insert. Once they're
+    /// all applied, each placeholder's index is also the index of its item in
+    /// the vec.
+    #[must_use]
+    pub fn take(&self) -> Vec<ListMutation> {
+        self.vec.items.with(|_| ());
+        let mut mutations = (0..self.existing.replace(0))
+            .map(ListMutation::Insert)
+            .collect::<Vec<_>>();
+        mutations.extend(self.vec.log.borrow_mut().take(self.id));
+        mutations
+    }
+
+    /// The vec this subscription is to.
+    #[must_use]
+    pub fn vec(&self) -> &ReactiveVec<T> {
+        &self.vec
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        self.vec.log.borrow_mut().unsubscribe(self.id);
+    }
+}
+
+/// Mutations that haven't been taken by every subscription yet.
+#[derive(Default)]
+struct Log {
+    /// The sequence number of the first mutation is `start`.
+    mutations: VecDeque<ListMutation>,
+    start: u64,
+    /// Maps each subscription's id to the sequence number of the next mutation
+    /// it will take.
+    cursors: HashMap<u64, u64>,
+    next_id: u64,
+}
+
+impl Log {
+    fn end(&self) -> u64 {
+        self.start + self.mutations.len() as u64
+    }
+
+    fn subscribe(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.cursors.insert(id, self.end());
+        id
+    }
+
+    fn unsubscribe(&mut self, id: u64) {
+        self.cursors.remove(&id);
+        self.trim();
+    }
+
+    fn record(&mut self, mutations: Vec<ListMutation>) {
+        // Nobody would ever take them.
+        if self.cursors.is_empty() {
+            return;
+        }
+        self.mutations.extend(mutations);
+    }
+
+    fn take(&mut self, id: u64) -> Vec<ListMutation> {
+        let end = self.end();
+        let cursor = self.cursors.insert(id, end).unwrap();
+        #[allow(clippy::cast_possible_truncation)]
+        let taken = self
+            .mutations
+            .iter()
+            .skip((cursor - self.start) as usize)
+            .copied()
+            .collect();
+        self.trim();
+        taken
+    }
+
+    /// Forgets the mutations every subscription has already taken.
+    fn trim(&mut self) {
+        let keep_from = self
+            .cursors
+            .values()
+            .copied()
+            .min()
+            .unwrap_or_else(|| self.end());
+        #[allow(clippy::cast_possible_truncation)]
+        self.mutations.drain(..(keep_from - self.start) as usize);
+        self.start = keep_from;
+    }
+}
+
+fn resolve(range: &impl RangeBounds<usize>, len: usize) -> Range<usize> {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => {
+            start
+                .checked_add(1)
+                .expect("attempted to index slice from after maximum usize")
+        }
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => {
+            end.checked_add(1)
+                .expect("attempted to index slice up to maximum usize")
+        }
+        Bound::Excluded(&end) => end,
+        Bound::Unbounded => len,
+    };
+    start..end
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        collections::{ListMutation, ReactiveVec, Subscription},
+        instance::Engine,
+    };
+    use std::{
+        cell::RefCell,
+        panic::{self, AssertUnwindSafe},
+        rc::Rc,
+    };
+
+    /// Keeps a copy of the vec up to date the way a view would, to check that
+    /// the log describes every change.
+    fn replay<T: Clone + 'static>(copy: &mut Vec<T>, subscription: &Subscription<T>) {
+        let mut slots = copy.drain(..).map(Some).collect::<Vec<_>>();
+        for mutation in subscription.take() {
+            match mutation {
+                ListMutation::Insert(index) => slots.insert(index, None),
+                ListMutation::Remove(index) => {
+                    slots.remove(index);
+                }
+            }
+        }
+        let items = subscription.vec().sample();
+        assert_eq!(slots.len(), items.len());
+        copy.extend(
+            slots
+                .into_iter()
+                .enumerate()
+                .map(|(index, slot)| slot.unwrap_or_else(|| items[index].clone())),
+        );
+    }
+
+    #[test]
+    fn logs_each_mutation() {
+        let engine = Rc::new(Engine::new());
+        let vec = ReactiveVec::from_vec(engine, vec![1, 2, 3]);
+        let subscription = vec.subscribe();
+        assert_eq!(subscription.take(), [
+            ListMutation::Insert(0),
+            ListMutation::Insert(1),
+            ListMutation::Insert(2),
+        ]);
+
+        vec.push(4);
+        vec.insert(0, 0);
+        assert_eq!(vec.pop(), Some(4));
+        assert_eq!(vec.remove(1), 1);
+        assert_eq!(subscription.take(), [
+            ListMutation::Insert(3),
+            ListMutation::Insert(0),
+            ListMutation::Remove(4),
+            ListMutation::Remove(1),
+        ]);
+        assert_eq!(subscription.take(), []);
+
+        assert_eq!(vec.set(0, 10), 0);
+        vec.move_item(0, 2);
+        assert_eq!(&*vec.sample(), [2, 3, 10]);
+        assert_eq!(subscription.take(), [
+            ListMutation::Remove(0),
+            ListMutation::Insert(0),
+            ListMutation::Remove(0),
+            ListMutation::Insert(2),
+        ]);
+
+        vec.extend(vec![4, 5]);
+        vec.truncate(3);
+        vec.clear();
+        assert_eq!(subscription.take(), [
+            ListMutation::Insert(3),
+            ListMutation::Insert(4),
+            ListMutation::Remove(4),
+            ListMutation::Remove(3),
+            ListMutation::Remove(2),
+            ListMutation::Remove(1),
+            ListMutation::Remove(0),
+        ]);
+    }
+
+    #[test]
+    fn replaying_the_log_reproduces_the_vec() {
+        let engine = Rc::new(Engine::new());
+        let vec = ReactiveVec::from_vec(engine, vec![5, 1, 4]);
+        let subscription = vec.subscribe();
+        let mut copy = Vec::new();
+        replay(&mut copy, &subscription);
+        assert_eq!(copy, [5, 1, 4]);
+
+        vec.push(2);
+        vec.insert(0, 3);
+        vec.insert(0, 9);
+        vec.swap(1, 4);
+        vec.set(2, 7);
+        replay(&mut copy, &subscription);
+        assert_eq!(copy, &*vec.sample());
+
+        vec.retain(|&x| x != 7 && x != 4);
+        vec.move_item(3, 0);
+        vec.sort_by(Ord::cmp);
+        replay(&mut copy, &subscription);
+        assert_eq!(copy, &*vec.sample());
+
+        assert_eq!(vec.splice(1..3, vec![6, 6, 6]), [2, 3]);
+        assert_eq!(vec.drain(..=1), [1, 6]);
+        vec.extend(vec![8, 0]);
+        replay(&mut copy, &subscription);
+        assert_eq!(copy, &*vec.sample());
+        assert_eq!(copy, [6, 6, 9, 8, 0]);
+    }
+
+    #[test]
+    fn sort_only_logs_what_moved() {
+        let engine = Rc::new(Engine::new());
+        let vec = ReactiveVec::from_vec(engine, vec![1, 3, 2, 4]);
+        let subscription = vec.subscribe();
+        drop(subscription.take());
+
+        vec.sort_by(Ord::cmp);
+        assert_eq!(subscription.take(), [
+            ListMutation::Remove(2),
+            ListMutation::Remove(1),
+            ListMutation::Insert(1),
+            ListMutation::Insert(2),
+        ]);
+        vec.sort_by(Ord::cmp);
+        assert_eq!(subscription.take(), []);
+    }
+
+    #[test]
+    fn subscriptions_are_independent() {
+        let engine = Rc::new(Engine::new());
+        let vec = ReactiveVec::new(engine);
+        let a = vec.subscribe();
+        vec.push(1);
+        let b = vec.subscribe();
+        vec.push(2);
+
+        assert_eq!(a.take(), [ListMutation::Insert(0), ListMutation::Insert(1)]);
+        vec.push(3);
+        assert_eq!(b.take(), [
+            ListMutation::Insert(0),
+            ListMutation::Insert(1),
+            ListMutation::Insert(2),
+        ]);
+        assert_eq!(a.take(), [ListMutation::Insert(2)]);
+
+        // Once every subscription has caught up, nothing is kept.
+        assert_eq!(vec.log.borrow().mutations.len(), 0);
+        drop(b);
+        vec.push(4);
+        assert_eq!(vec.log.borrow().mutations.len(), 1);
+        drop(a);
+        assert_eq!(vec.log.borrow().mutations.len(), 0);
+        vec.push(5);
+        assert_eq!(vec.log.borrow().mutations.len(), 0);
+    }
+
+    #[test]
+    fn reactions_rerun_on_changes() {
+        let engine = Rc::new(Engine::new());
+        let vec = ReactiveVec::new(engine.clone());
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let subscription = vec.subscribe();
+            let sink = sink.clone();
+            move || sink.borrow_mut().push(subscription.take())
+        });
+        vec.push('a');
+        // Nothing changes, so nothing runs.
+        vec.truncate(5);
+        vec.retain(|_| true);
+
+        let batch = engine.batch();
+        vec.push('b');
+        vec.remove(0);
+        drop(batch);
+        assert_eq!(*sink.borrow(), [
+            vec![],
+            vec![ListMutation::Insert(0)],
+            vec![ListMutation::Insert(1), ListMutation::Remove(0)],
+        ]);
+    }
+
+    #[test]
+    fn callbacks_can_read_the_vec() {
+        let engine = Rc::new(Engine::new());
+        let vec = ReactiveVec::from_vec(engine, vec![3, 1, 2]);
+        vec.retain(|&x| x != vec.len());
+        assert_eq!(&*vec.sample(), [1, 2]);
+        vec.sort_by(|a, b| {
+            assert_eq!(vec.len(), 2);
+            b.cmp(a)
+        });
+        assert_eq!(&*vec.sample(), [2, 1]);
+        vec.extend((0..2).map(|_| vec.len()));
+        assert_eq!(&*vec.sample(), [2, 1, 2, 2]);
+        vec.splice(..1, (0..1).map(|_| vec.len()));
+        assert_eq!(&*vec.sample(), [4, 1, 2, 2]);
+    }
+
+    #[test]
+    fn panicking_comparator_leaves_the_vec_alone() {
+        let engine = Rc::new(Engine::new());
+        let vec = ReactiveVec::from_vec(engine, vec![3, 1, 2]);
+        let subscription = vec.subscribe();
+        drop(subscription.take());
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            vec.sort_by(|_, _| panic!("nope"));
+        }));
+        assert!(result.is_err());
+        assert_eq!(&*vec.sample(), [3, 1, 2]);
+        assert_eq!(subscription.take(), []);
+    }
+
+    #[test]
+    #[should_panic(expected = "attempted to index slice up to maximum usize")]
+    fn inclusive_range_to_max_panics() {
+        let engine = Rc::new(Engine::new());
+        let vec = ReactiveVec::from_vec(engine, vec![1]);
+        vec.drain(..=usize::MAX);
+    }
+
+    #[test]
+    fn failed_operations_notify_nobody() {
+        let engine = Rc::new(Engine::new());
+        let vec = ReactiveVec::from_vec(engine.clone(), vec![1]);
+        let runs = Rc::new(RefCell::new(0));
+        engine.react({
+            let subscription = vec.subscribe();
+            let runs = runs.clone();
+            move || {
+                drop(subscription.take());
+                *runs.borrow_mut() += 1;
+            }
+        });
+        let subscription = vec.subscribe();
+        drop(subscription.take());
+
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| vec.remove(5))).is_err());
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| vec.insert(5, 2))).is_err());
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| vec.swap(0, 5))).is_err());
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| vec.move_item(0, 5))).is_err());
+        assert_eq!(*runs.borrow(), 1);
+        assert_eq!(subscription.take(), []);
+        assert_eq!(&*vec.sample(), [1]);
+    }
+}
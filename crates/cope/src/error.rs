@@ -0,0 +1,52 @@
+use std::{error, fmt};
+
+/// Something that went wrong while reading or writing an atom or computed
+/// value.
+///
+/// Each variant names what was being accessed, either by the name given with
+/// `named`, or by its type.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The value is already borrowed in a way that conflicts with this access,
+    /// for example a `get` guard is still alive during a `set`.
+    AlreadyBorrowed { name: String },
+    /// The computed value was disposed by its owner, so it won't update
+    /// anymore.
+    Disposed { name: String },
+    /// The value belongs to a different engine than the one that's running.
+    WrongEngine { name: String },
+    /// Some reaction ran more than the engine's maximum number of iterations
+    /// during a single update. `names` lists the atoms written since it last
+    /// ran, which are probably part of the cycle.
+    CycleDetected {
+        max_iterations: u32,
+        names: Vec<String>,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::AlreadyBorrowed { name } => write!(f, "{} is already borrowed", name),
+            Error::Disposed { name } => write!(f, "{} was disposed", name),
+            Error::WrongEngine { name } => {
+                write!(f, "{} was accessed from a different engine", name)
+            }
+            Error::CycleDetected {
+                max_iterations,
+                names,
+            } => {
+                write!(
+                    f,
+                    "a reaction ran more than {} times in a single update, so it's probably in a \
+                     cycle. Atoms written since it last ran: {}",
+                    max_iterations,
+                    names.join(", "),
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
@@ -0,0 +1,136 @@
+use std::{
+    convert::TryFrom,
+    ops::{Index, IndexMut},
+};
+
+/// Identifies a value in an [`Arena`].
+///
+/// Each slot has a generation that's bumped whenever its value is removed, so a
+/// key to a removed value never finds whatever is stored there next.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Key {
+    index: u32,
+    generation: u32,
+}
+
+/// A slot map. Inserting and removing are O(1), and freed slots are reused.
+pub(crate) struct Arena<T> {
+    slots: Vec<Slot<T>>,
+    /// Indices of empty slots.
+    free: Vec<u32>,
+    len: usize,
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+impl<T> Arena<T> {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn insert(&mut self, value: T) -> Key {
+        self.len += 1;
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.value = Some(value);
+            return Key {
+                index,
+                generation: slot.generation,
+            };
+        }
+
+        let index = u32::try_from(self.slots.len()).expect("too many arena slots");
+        self.slots.push(Slot {
+            generation: 0,
+            value: Some(value),
+        });
+        Key {
+            index,
+            generation: 0,
+        }
+    }
+
+    pub fn remove(&mut self, key: Key) -> Option<T> {
+        let slot = self.slots.get_mut(key.index as usize)?;
+        if slot.generation != key.generation {
+            return None;
+        }
+        let value = slot.value.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(key.index);
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn get(&self, key: Key) -> Option<&T> {
+        let slot = self.slots.get(key.index as usize)?;
+        if slot.generation != key.generation {
+            return None;
+        }
+        slot.value.as_ref()
+    }
+
+    pub fn get_mut(&mut self, key: Key) -> Option<&mut T> {
+        let slot = self.slots.get_mut(key.index as usize)?;
+        if slot.generation != key.generation {
+            return None;
+        }
+        slot.value.as_mut()
+    }
+}
+
+impl<T> Index<Key> for Arena<T> {
+    type Output = T;
+
+    fn index(&self, key: Key) -> &T {
+        self.get(key).expect("stale arena key")
+    }
+}
+
+impl<T> IndexMut<Key> for Arena<T> {
+    fn index_mut(&mut self, key: Key) -> &mut T {
+        self.get_mut(key).expect("stale arena key")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instance::arena::Arena;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut arena = Arena::new();
+        let a = arena.insert("a");
+        let b = arena.insert("b");
+        assert_eq!(arena.len(), 2);
+        assert_eq!(arena[a], "a");
+        assert_eq!(arena.remove(a), Some("a"));
+        assert_eq!(arena.remove(a), None);
+        assert_eq!(arena.get(a), None);
+        assert_eq!(arena[b], "b");
+        assert_eq!(arena.len(), 1);
+    }
+
+    #[test]
+    fn reused_slots_reject_stale_keys() {
+        let mut arena = Arena::new();
+        let a = arena.insert(1);
+        arena.remove(a);
+        let b = arena.insert(2);
+        assert_ne!(a, b);
+        assert_eq!(arena.get(a), None);
+        assert_eq!(arena[b], 2);
+    }
+}
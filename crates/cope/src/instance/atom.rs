@@ -1,67 +1,370 @@
-use crate::instance::engine::Engine;
+#[cfg(feature = "futures")]
+use crate::instance::{Changed, Values};
+use crate::{
+    instance::{Engine, SourceId},
+    Error,
+};
 use std::{
+    any,
+    borrow::Cow,
     cell::{Ref, RefCell, RefMut},
+    mem,
     ops::{Deref, DerefMut},
-    sync::Arc,
+    rc::{Rc, Weak},
+    thread,
 };
 
-type SubscriptionList = Vec<Arc<RefCell<Vec<Subscription>>>>;
-type Subscription = Arc<RefCell<dyn FnMut()>>;
-
+/// A value that reactions can subscribe to.
+///
+/// An atom doesn't keep its engine alive, so reactions that capture one don't
+/// keep themselves alive either. Once the engine is dropped, the atom still
+/// holds its value, but nothing reacts to it anymore.
 pub struct Atom<T> {
-    engine: Arc<Engine>,
-    value: Arc<RefCell<T>>,
-    subscriptions: Arc<RefCell<SubscriptionList>>,
+    inner: Rc<Inner<T>>,
+}
+
+struct Inner<T> {
+    engine: Weak<Engine>,
+    source: SourceId,
+    value: RefCell<T>,
+    /// Decides whether `set` changed anything. Without one, it always notifies.
+    equality: Option<fn(&T, &T) -> bool>,
 }
 
 impl<T: 'static> Atom<T> {
-    pub fn new(engine: Arc<Engine>, initial_value: T) -> Self {
+    pub fn new(engine: Rc<Engine>, initial_value: T) -> Self {
+        Self::with_equality_option(engine, initial_value, None)
+    }
+
+    /// Creates an atom whose [`set`] and [`overwrite`] use `equality` to decide
+    /// whether the value changed, and skip notifying subscribers if it didn't.
+    /// With [`overwrite`], this works for any `T`, not just ones that implement
+    /// `PartialEq`.
+    ///
+    /// [`set`]: Atom::set
+    /// [`overwrite`]: Atom::overwrite
+    pub fn with_equality(
+        engine: Rc<Engine>,
+        initial_value: T,
+        equality: fn(&T, &T) -> bool,
+    ) -> Self {
+        Self::with_equality_option(engine, initial_value, Some(equality))
+    }
+
+    // Takes an `Rc` like the other constructors, even though it only keeps a
+    // weak reference.
+    #[allow(clippy::needless_pass_by_value)]
+    fn with_equality_option(
+        engine: Rc<Engine>,
+        initial_value: T,
+        equality: Option<fn(&T, &T) -> bool>,
+    ) -> Self {
+        let source = engine.add_source(any::type_name::<Self>());
         Self {
-            engine,
-            value: Arc::new(RefCell::new(initial_value)),
-            subscriptions: Arc::new(RefCell::new(Vec::new())),
+            inner: Rc::new(Inner {
+                engine: Rc::downgrade(&engine),
+                source,
+                value: RefCell::new(initial_value),
+                equality,
+            }),
+        }
+    }
+
+    /// Gives the atom a name, which is used to identify it in error messages.
+    #[must_use]
+    pub fn named(self, name: impl Into<Cow<'static, str>>) -> Self {
+        if let Some(engine) = self.engine() {
+            engine.set_name(self.inner.source, name.into());
+        }
+        self
+    }
+
+    /// Identifies this atom within its engine. Clones share the same id.
+    #[must_use]
+    pub fn id(&self) -> SourceId {
+        self.inner.source
+    }
+
+    /// The atom's engine, unless it has been dropped.
+    pub(crate) fn engine(&self) -> Option<Rc<Engine>> {
+        self.inner.engine.upgrade()
+    }
+
+    /// Creates a handle that doesn't keep the atom alive.
+    ///
+    /// Reactions that capture an atom keep it alive for as long as they run.
+    /// Capture a weak handle instead to let the atom be freed once every other
+    /// clone is dropped. Reactions that depended only on it are disposed at
+    /// that point.
+    #[must_use]
+    pub fn downgrade(&self) -> WeakAtom<T> {
+        WeakAtom {
+            inner: Rc::downgrade(&self.inner),
         }
     }
 
     #[must_use]
     pub fn get(&self) -> Ref<'_, T> {
-        self.engine.track(&self.subscriptions);
-        self.value.borrow()
+        self.try_get().unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like [`get`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AlreadyBorrowed`] if the value is currently borrowed
+    /// mutably, or [`Error::WrongEngine`] if a reaction from a different engine
+    /// is running.
+    ///
+    /// [`get`]: Atom::get
+    pub fn try_get(&self) -> Result<Ref<'_, T>, Error> {
+        let value = self
+            .inner
+            .value
+            .try_borrow()
+            .map_err(|_| self.already_borrowed())?;
+        if let Some(engine) = self.engine() {
+            engine.track(self.inner.source)?;
+        }
+        Ok(value)
+    }
+
+    /// Calls `f` with a reference to the value, and subscribes the current
+    /// reaction to it.
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&self.get())
+    }
+
+    /// Returns a clone of the value, and subscribes the current reaction to it.
+    #[must_use]
+    pub fn get_cloned(&self) -> T
+    where
+        T: Clone,
+    {
+        self.get().clone()
+    }
+
+    /// Reads the value without subscribing the current reaction to it.
+    #[must_use]
+    pub fn sample(&self) -> Ref<'_, T> {
+        self.inner.value.borrow()
     }
 
     #[must_use]
     pub fn get_mut(&self) -> AtomMut<'_, T> {
-        AtomMut {
-            value: Some(self.value.borrow_mut()),
-            subscriptions: self.subscriptions.clone(),
-        }
+        self.try_get_mut()
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like [`get_mut`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AlreadyBorrowed`] if the value is currently borrowed.
+    ///
+    /// [`get_mut`]: Atom::get_mut
+    pub fn try_get_mut(&self) -> Result<AtomMut<'_, T>, Error> {
+        let value = self
+            .inner
+            .value
+            .try_borrow_mut()
+            .map_err(|_| self.already_borrowed())?;
+        Ok(AtomMut {
+            engine: &self.inner.engine,
+            value: Some(value),
+            source: self.inner.source,
+            changed: true,
+        })
     }
 
     #[must_use]
     pub fn sample_mut(&self) -> RefMut<'_, T> {
-        self.value.borrow_mut()
+        self.inner.value.borrow_mut()
     }
 
-    pub fn set(&self, value: T) {
-        *self.get_mut() = value;
+    /// Calls `f` with a mutable reference to the value, then notifies
+    /// subscribers once it returns.
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.get_mut())
+    }
+
+    /// Replaces the value and returns the old one. Unlike [`set`], this always
+    /// notifies subscribers.
+    ///
+    /// [`set`]: Atom::set
+    pub fn replace(&self, value: T) -> T {
+        self.update(|old| mem::replace(old, value))
+    }
+
+    /// Takes the value, leaving the default in its place.
+    #[must_use]
+    pub fn take(&self) -> T
+    where
+        T: Default,
+    {
+        self.replace(T::default())
+    }
+
+    /// Replaces the value and notifies subscribers, unless the new value is
+    /// equal to the old one. Equality is decided by the comparator from
+    /// [`with_equality`] if there is one, and by `PartialEq` otherwise.
+    ///
+    /// [`with_equality`]: Atom::with_equality
+    pub fn set(&self, value: T)
+    where
+        T: PartialEq,
+    {
+        if let Err(error) = self.try_set(value) {
+            panic!("{}", error);
+        }
+    }
+
+    /// Like [`set`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AlreadyBorrowed`] if the value is currently borrowed,
+    /// or [`Error::CycleDetected`] if the reactions this write triggers don't
+    /// settle down. Inside a batch or a reaction, the reactions run later, so
+    /// any cycle is reported by whatever started the update instead.
+    ///
+    /// [`set`]: Atom::set
+    pub fn try_set(&self, value: T) -> Result<(), Error>
+    where
+        T: PartialEq,
+    {
+        let equality = self.inner.equality.unwrap_or(T::eq);
+        self.try_set_unless(value, Some(equality))
+    }
+
+    /// Like [`set`], but works for any `T`. Only the comparator from
+    /// [`with_equality`] is consulted, so without one, subscribers are always
+    /// notified.
+    ///
+    /// [`set`]: Atom::set
+    /// [`with_equality`]: Atom::with_equality
+    pub fn overwrite(&self, value: T) {
+        if let Err(error) = self.try_overwrite(value) {
+            panic!("{}", error);
+        }
+    }
+
+    /// Like [`overwrite`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// The same as [`try_set`].
+    ///
+    /// [`overwrite`]: Atom::overwrite
+    /// [`try_set`]: Atom::try_set
+    pub fn try_overwrite(&self, value: T) -> Result<(), Error> {
+        self.try_set_unless(value, self.inner.equality)
+    }
+
+    /// Writes `value`, unless `equality` says it's the same as the old one.
+    fn try_set_unless(&self, value: T, equality: Option<fn(&T, &T) -> bool>) -> Result<(), Error> {
+        let mut guard = self.try_get_mut()?;
+        // Notify manually below, so that errors can be returned.
+        AtomMut::mark_unchanged(&mut guard);
+        if let Some(equality) = equality {
+            if equality(&guard, &value) {
+                return Ok(());
+            }
+        }
+        *guard = value;
+        drop(guard);
+        match self.engine() {
+            Some(engine) => engine.try_write(self.inner.source),
+            None => Ok(()),
+        }
+    }
+
+    fn already_borrowed(&self) -> Error {
+        Error::AlreadyBorrowed {
+            name: Engine::describe_weak(&self.inner.engine, self.inner.source),
+        }
     }
 }
 
 impl<T> Clone for Atom<T> {
     fn clone(&self) -> Self {
         Self {
-            engine: self.engine.clone(),
-            value: self.value.clone(),
-            subscriptions: self.subscriptions.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<T: 'static> Atom<T> {
+    /// Returns a future that resolves the next time the atom is written.
+    pub fn changed(&self) -> Changed {
+        Changed::new(&self.inner.engine, self.inner.source)
+    }
+
+    /// Returns a stream of the atom's values, starting with the current one.
+    /// Writes between polls are coalesced. The stream ends once every other
+    /// handle to the atom is dropped.
+    pub fn stream(&self) -> Values<T>
+    where
+        T: Clone,
+    {
+        let atom = self.downgrade();
+        Values::new(&self.inner.engine, self.inner.source, move || {
+            atom.upgrade().map(|atom| atom.sample().clone())
+        })
+    }
+}
+
+/// A handle to an atom that doesn't keep it alive. See [`Atom::downgrade`].
+#[allow(clippy::module_name_repetitions)]
+pub struct WeakAtom<T> {
+    inner: Weak<Inner<T>>,
+}
+
+impl<T> WeakAtom<T> {
+    /// Returns the atom, or `None` if every strong handle has been dropped.
+    #[must_use]
+    pub fn upgrade(&self) -> Option<Atom<T>> {
+        Some(Atom {
+            inner: self.inner.upgrade()?,
+        })
+    }
+}
+
+impl<T> Clone for WeakAtom<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        // If the engine is gone, so is the graph.
+        if let Some(engine) = self.engine.upgrade() {
+            engine.remove_source(self.source);
         }
     }
 }
 
 #[allow(clippy::module_name_repetitions)]
 pub struct AtomMut<'a, T> {
+    engine: &'a Weak<Engine>,
     // Option dance
     value: Option<RefMut<'a, T>>,
-    subscriptions: Arc<RefCell<SubscriptionList>>,
+    source: SourceId,
+    changed: bool,
+}
+
+impl<T> AtomMut<'_, T> {
+    /// Declares that the value didn't actually change, so nothing is notified
+    /// when the guard is dropped.
+    ///
+    /// This is an associated function so it doesn't shadow methods on `T`.
+    pub fn mark_unchanged(this: &mut Self) {
+        this.changed = false;
+    }
 }
 
 impl<T> Deref for AtomMut<'_, T> {
@@ -81,32 +384,42 @@ impl<T> DerefMut for AtomMut<'_, T> {
 impl<T> Drop for AtomMut<'_, T> {
     fn drop(&mut self) {
         drop(self.value.take());
+        // If whoever held the guard panicked, the value may be half-written,
+        // so don't tell anyone about it, and don't run reactions mid-unwind.
+        if !self.changed || thread::panicking() {
+            return;
+        }
 
-        for subscriptions in self.subscriptions.borrow().iter() {
-            for subscription in subscriptions.borrow_mut().iter_mut() {
-                let mut func = subscription.borrow_mut();
-                // https://github.com/rust-lang/rust/issues/51886
-                (&mut *func)();
-            }
+        // If we're already inside a batch or a reaction, this only queues the
+        // reactions, and they run once that finishes.
+        if let Some(engine) = self.engine.upgrade() {
+            engine.write(self.source);
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::instance::{Atom, Engine};
-    use std::sync::Arc;
+    use crate::{
+        instance::{Atom, AtomMut, Engine},
+        Error,
+    };
+    use std::{
+        cell::RefCell,
+        panic::{self, AssertUnwindSafe},
+        rc::Rc,
+    };
 
     #[test]
     fn get_initial_value() {
-        let engine = Arc::new(Engine::new());
+        let engine = Rc::new(Engine::new());
         let atom = Atom::new(engine, 123);
         assert_eq!(*atom.get(), 123);
     }
 
     #[test]
     fn set() {
-        let engine = Arc::new(Engine::new());
+        let engine = Rc::new(Engine::new());
         let atom = Atom::new(engine, 0);
         atom.set(42);
         assert_eq!(*atom.get(), 42);
@@ -114,9 +427,244 @@ mod tests {
 
     #[test]
     fn mutate() {
-        let engine = Arc::new(Engine::new());
+        let engine = Rc::new(Engine::new());
         let atom = Atom::new(engine, 10);
         *atom.get_mut() += 1;
         assert_eq!(*atom.get(), 11);
     }
+
+    #[test]
+    fn set_equal_value_does_not_notify() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || {
+                sink.borrow_mut().push(*atom.get());
+            }
+        });
+        atom.set(1);
+        atom.set(2);
+        atom.set(2);
+        assert_eq!(*sink.borrow(), [1, 2]);
+
+        // `overwrite` has nothing to compare with, so it always notifies.
+        atom.overwrite(2);
+        assert_eq!(*sink.borrow(), [1, 2, 2]);
+    }
+
+    #[test]
+    fn with_equality() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::with_equality(engine.clone(), 10, |a, b| a / 10 == b / 10);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || {
+                sink.borrow_mut().push(*atom.get());
+            }
+        });
+        atom.set(15);
+        atom.overwrite(19);
+        atom.set(25);
+        assert_eq!(*sink.borrow(), [10, 25]);
+    }
+
+    #[test]
+    fn with_equality_without_partial_eq() {
+        struct Id(u32);
+
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::with_equality(engine.clone(), Id(1), |a, b| a.0 == b.0);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            // Every handle shares the comparator, weak ones included.
+            let atom = atom.downgrade().upgrade().unwrap();
+            let sink = sink.clone();
+            move || sink.borrow_mut().push(atom.get().0)
+        });
+        atom.overwrite(Id(1));
+        atom.overwrite(Id(2));
+        assert_eq!(*sink.borrow(), [1, 2]);
+    }
+
+    #[test]
+    fn mark_unchanged() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), vec![1]);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || {
+                sink.borrow_mut().push(atom.get().len());
+            }
+        });
+        {
+            let mut guard = atom.get_mut();
+            guard.retain(|&x| x != 2);
+            AtomMut::mark_unchanged(&mut guard);
+        }
+        atom.get_mut().push(2);
+        assert_eq!(*sink.borrow(), [1, 2]);
+    }
+
+    #[test]
+    fn try_set_while_borrowed() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 0).named("count");
+        let guard = atom.get();
+        let error = atom.try_set(1).unwrap_err();
+        assert_eq!(error, Error::AlreadyBorrowed {
+            name: "`count`".into(),
+        },);
+        assert_eq!(error.to_string(), "`count` is already borrowed");
+        drop(guard);
+        assert_eq!(atom.try_set(1), Ok(()));
+        assert!(atom.try_get_mut().is_ok());
+        // The atom doesn't keep the engine alive, and its name lives there.
+        drop(engine);
+    }
+
+    #[test]
+    fn try_get_while_borrowed_mut() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine, 0);
+        let guard = atom.get_mut();
+        assert!(matches!(atom.try_get(), Err(Error::AlreadyBorrowed { .. })));
+        drop(guard);
+        assert_eq!(*atom.try_get().unwrap(), 0);
+    }
+
+    #[test]
+    fn try_set_reports_cycles() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 0).named("count");
+        engine.set_max_iterations(3);
+        engine.react({
+            let atom = atom.clone();
+            move || {
+                let n = *atom.get();
+                if n > 0 {
+                    drop(atom.try_set(n + 1));
+                }
+            }
+        });
+        assert_eq!(
+            atom.try_set(1),
+            Err(Error::CycleDetected {
+                max_iterations: 3,
+                names: vec!["`count`".into()],
+            }),
+        );
+    }
+
+    #[test]
+    fn closure_accessors() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), vec![1, 2]);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || sink.borrow_mut().push(atom.with(Vec::len))
+        });
+
+        assert_eq!(atom.update(Vec::pop), Some(2));
+        assert_eq!(atom.replace(vec![4, 5, 6]), [1]);
+        assert_eq!(atom.get_cloned(), [4, 5, 6]);
+        assert_eq!(atom.take(), [4, 5, 6]);
+        assert_eq!(*sink.borrow(), [2, 1, 3, 0]);
+    }
+
+    #[test]
+    fn dropping_atom_frees_nodes() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let other = Atom::new(engine.clone(), 1);
+        let captured = Rc::new(());
+        let reaction = engine.react({
+            let atom = atom.downgrade();
+            let captured = captured.clone();
+            move || {
+                let _ = (atom.upgrade().map(|atom| *atom.get()), &captured);
+            }
+        });
+        // This one still depends on `other`, so it survives.
+        engine.react({
+            let atom = atom.downgrade();
+            move || {
+                let _ = (atom.upgrade().map(|atom| *atom.get()), *other.get());
+            }
+        });
+        assert_eq!(engine.node_counts(), (2, 2));
+
+        drop(atom);
+        assert_eq!(engine.node_counts(), (1, 1));
+        assert!(reaction.is_disposed());
+        assert_eq!(Rc::strong_count(&captured), 1);
+    }
+
+    #[test]
+    fn dropping_every_handle_frees_engine() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let atom = atom.downgrade();
+            let sink = sink.clone();
+            move || {
+                sink.borrow_mut()
+                    .extend(atom.upgrade().map(|atom| *atom.get()))
+            }
+        });
+        atom.set(2);
+        assert_eq!(*sink.borrow(), [1, 2]);
+
+        let weak = Rc::downgrade(&engine);
+        drop(engine);
+        drop(atom);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn panic_while_mutating_does_not_notify() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || sink.borrow_mut().push(*atom.get())
+        });
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            atom.update(|value| {
+                *value = 2;
+                panic!("nope");
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(*sink.borrow(), [1]);
+    }
+
+    #[test]
+    fn outlives_engine() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || sink.borrow_mut().push(*atom.get())
+        });
+        drop(engine);
+        // Nothing reacts anymore, but the value is still there.
+        atom.set(2);
+        *atom.get_mut() += 1;
+        assert_eq!(*atom.get(), 3);
+        assert_eq!(*sink.borrow(), [1]);
+    }
 }
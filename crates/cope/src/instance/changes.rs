@@ -0,0 +1,286 @@
+use crate::instance::{Engine, ReactionGuard, SourceId};
+use futures_core::Stream;
+use std::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    rc::{Rc, Weak},
+    task::{Context, Poll, Waker},
+};
+
+/// A future that resolves the next time an atom or computed value changes.
+///
+/// It also resolves if the atom or its engine is dropped first, since it can
+/// never change after that.
+///
+/// Returned by [`Atom::changed`] and [`Computed::changed`].
+///
+/// [`Atom::changed`]: crate::instance::Atom::changed
+/// [`Computed::changed`]: crate::instance::Computed::changed
+#[must_use = "futures do nothing unless polled"]
+pub struct Changed {
+    shared: Rc<RefCell<Shared>>,
+    _watcher: Option<ReactionGuard>,
+}
+
+impl Changed {
+    pub(crate) fn new(engine: &Weak<Engine>, source: SourceId) -> Self {
+        let (shared, watcher) = watch(engine, source, false);
+        Self {
+            shared,
+            _watcher: watcher,
+        }
+    }
+}
+
+impl Future for Changed {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut shared = self.shared.borrow_mut();
+        if shared.changed || shared.closed {
+            return Poll::Ready(());
+        }
+        shared.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// A stream of an atom or computed value's values.
+///
+/// The first item is the current value. After that, each item is the latest
+/// value after one or more changes, so changes that happen between polls are
+/// coalesced into one item.
+///
+/// Returned by [`Atom::stream`], [`Computed::stream`] and [`Engine::stream`].
+///
+/// [`Atom::stream`]: crate::instance::Atom::stream
+/// [`Computed::stream`]: crate::instance::Computed::stream
+#[must_use = "streams do nothing unless polled"]
+pub struct Values<T> {
+    shared: Rc<RefCell<Shared>>,
+    /// Reads the current value, or returns `None` once there's nothing left to
+    /// read.
+    read: Box<dyn FnMut() -> Option<T>>,
+    _watcher: Option<ReactionGuard>,
+}
+
+impl<T> Values<T> {
+    pub(crate) fn new(
+        engine: &Weak<Engine>,
+        source: SourceId,
+        read: impl FnMut() -> Option<T> + 'static,
+    ) -> Self {
+        let (shared, watcher) = watch(engine, source, true);
+        Self {
+            shared,
+            read: Box::new(read),
+            _watcher: watcher,
+        }
+    }
+}
+
+impl<T> Stream for Values<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let this = self.get_mut();
+        {
+            let mut shared = this.shared.borrow_mut();
+            if shared.closed {
+                return Poll::Ready(None);
+            }
+            if !shared.changed {
+                shared.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            shared.changed = false;
+        }
+        Poll::Ready((this.read)())
+    }
+}
+
+impl Engine {
+    /// Like [`react`], but instead of running `f` for its side effects, yields
+    /// what it returns as a stream. `f` only runs when the stream is polled,
+    /// and only if something it read has changed since.
+    ///
+    /// [`react`]: Engine::react
+    pub fn stream<T: Clone + 'static>(
+        self: &Rc<Self>,
+        f: impl FnMut() -> T + 'static,
+    ) -> Values<T> {
+        crate::instance::Computed::new(self.clone(), f).stream()
+    }
+}
+
+/// What a watcher shares with the future or stream that owns it.
+#[derive(Default)]
+struct Shared {
+    changed: bool,
+    /// Whether the watcher was dropped, which means the source is gone.
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+/// Lives inside the watcher's closure.
+struct Notifier(Rc<RefCell<Shared>>);
+
+impl Notifier {
+    fn notify(&self, closed: bool) {
+        let waker = {
+            let mut shared = self.0.borrow_mut();
+            shared.changed = true;
+            shared.closed |= closed;
+            shared.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for Notifier {
+    /// The engine drops a watcher once its source is gone, so wake up whoever
+    /// is waiting, and let them find out.
+    fn drop(&mut self) {
+        self.notify(true);
+    }
+}
+
+/// Starts watching `source`. If the engine is already gone, nothing can
+/// change, so the result starts out closed.
+fn watch(
+    engine: &Weak<Engine>,
+    source: SourceId,
+    changed: bool,
+) -> (Rc<RefCell<Shared>>, Option<ReactionGuard>) {
+    let shared = Rc::new(RefCell::new(Shared {
+        changed,
+        ..Shared::default()
+    }));
+    let notifier = Notifier(shared.clone());
+    let watcher = engine.upgrade().map(|engine| {
+        engine
+            .watch(source, move || notifier.notify(false))
+            .into_guard()
+    });
+    (shared, watcher)
+}
+
+#[cfg(test)]
+// Clippy trips over the bindings generated for `.await`.
+#[allow(clippy::used_underscore_binding)]
+mod tests {
+    use crate::instance::{Atom, Computed, Engine};
+    use futures_executor::LocalPool;
+    use futures_util::{task::LocalSpawnExt, StreamExt};
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn changed_resolves_after_write() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let mut pool = LocalPool::new();
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        pool.spawner()
+            .spawn_local({
+                let atom = atom.clone();
+                let sink = sink.clone();
+                async move {
+                    atom.changed().await;
+                    sink.borrow_mut().push(*atom.sample());
+                }
+            })
+            .unwrap();
+
+        pool.run_until_stalled();
+        assert_eq!(*sink.borrow(), []);
+        atom.set(2);
+        atom.set(3);
+        pool.run_until_stalled();
+        assert_eq!(*sink.borrow(), [3]);
+        drop(engine);
+    }
+
+    #[test]
+    fn stream_coalesces_writes() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let mut pool = LocalPool::new();
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        pool.spawner()
+            .spawn_local({
+                let mut values = atom.stream();
+                let sink = sink.clone();
+                async move {
+                    while let Some(value) = values.next().await {
+                        sink.borrow_mut().push(value);
+                    }
+                    sink.borrow_mut().push(0);
+                }
+            })
+            .unwrap();
+
+        pool.run_until_stalled();
+        assert_eq!(*sink.borrow(), [1]);
+        atom.set(2);
+        atom.set(3);
+        pool.run_until_stalled();
+        assert_eq!(*sink.borrow(), [1, 3]);
+
+        // Dropping the atom ends the stream.
+        drop(atom);
+        pool.run_until_stalled();
+        assert_eq!(*sink.borrow(), [1, 3, 0]);
+        drop(engine);
+    }
+
+    #[test]
+    fn computed_and_engine_streams() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let doubled = Computed::new(engine.clone(), {
+            let atom = atom.clone();
+            move || *atom.get() * 2
+        });
+        let mut pool = LocalPool::new();
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let fired = Rc::new(RefCell::new(false));
+        pool.spawner()
+            .spawn_local({
+                let mut doubled = doubled.stream();
+                let mut tripled = engine.stream({
+                    let atom = atom.clone();
+                    move || *atom.get() * 3
+                });
+                let sink = sink.clone();
+                async move {
+                    loop {
+                        let doubled = doubled.next().await;
+                        let tripled = tripled.next().await;
+                        sink.borrow_mut().push((doubled, tripled));
+                    }
+                }
+            })
+            .unwrap();
+        pool.spawner()
+            .spawn_local({
+                let changed = doubled.changed();
+                let fired = fired.clone();
+                async move {
+                    changed.await;
+                    *fired.borrow_mut() = true;
+                }
+            })
+            .unwrap();
+
+        pool.run_until_stalled();
+        assert_eq!(*sink.borrow(), [(Some(2), Some(3))]);
+        assert!(!*fired.borrow());
+        atom.set(5);
+        pool.run_until_stalled();
+        assert_eq!(*sink.borrow(), [(Some(2), Some(3)), (Some(10), Some(15))]);
+        assert!(*fired.borrow());
+    }
+}
@@ -0,0 +1,391 @@
+#[cfg(feature = "futures")]
+use crate::instance::{Changed, Values};
+use crate::{
+    instance::{Engine, ObserverId, SourceId},
+    Error,
+};
+use std::{
+    any,
+    borrow::Cow,
+    cell::{Ref, RefCell},
+    rc::{Rc, Weak},
+};
+
+/// A value derived from other atoms.
+///
+/// The closure runs lazily the first time the value is read, and the result is
+/// cached until one of the atoms it read changes. Reading a computed value
+/// inside a reaction subscribes the reaction to it, just like an atom.
+///
+/// Like an atom, it doesn't keep its engine alive. Once the engine is dropped,
+/// reading it returns [`Error::Disposed`].
+pub struct Computed<T> {
+    inner: Rc<Inner<T>>,
+}
+
+struct Inner<T> {
+    engine: Weak<Engine>,
+    value: Rc<RefCell<Option<T>>>,
+    observer: ObserverId,
+    source: SourceId,
+}
+
+impl<T: 'static> Computed<T> {
+    // Takes an `Rc` like the other constructors, even though it only keeps a
+    // weak reference.
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn new(engine: Rc<Engine>, mut f: impl FnMut() -> T + 'static) -> Self {
+        let value = Rc::new(RefCell::new(None));
+        let source = engine.add_source(any::type_name::<Self>());
+        let observer = engine.add_computed(source, {
+            let value = value.clone();
+            move || {
+                let new_value = f();
+                *value.borrow_mut() = Some(new_value);
+            }
+        });
+        Self {
+            inner: Rc::new(Inner {
+                engine: Rc::downgrade(&engine),
+                value,
+                observer,
+                source,
+            }),
+        }
+    }
+
+    /// Gives the computed value a name, which is used to identify it in error
+    /// messages.
+    #[must_use]
+    pub fn named(self, name: impl Into<Cow<'static, str>>) -> Self {
+        if let Some(engine) = self.inner.engine.upgrade() {
+            engine.set_name(self.inner.source, name.into());
+        }
+        self
+    }
+
+    /// Identifies this value within its engine, as something reactions can
+    /// depend on.
+    #[must_use]
+    pub fn id(&self) -> SourceId {
+        self.inner.source
+    }
+
+    #[must_use]
+    pub fn get(&self) -> Ref<'_, T> {
+        self.try_get().unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like [`get`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Disposed`] if the reaction that owned this was
+    /// disposed or the engine was dropped, [`Error::AlreadyBorrowed`] if the
+    /// value is being written, or [`Error::WrongEngine`] if a reaction from
+    /// a different engine is running.
+    ///
+    /// [`get`]: Computed::get
+    pub fn try_get(&self) -> Result<Ref<'_, T>, Error> {
+        let value = self.try_sample()?;
+        if let Some(engine) = self.inner.engine.upgrade() {
+            engine.track(self.inner.source)?;
+        }
+        Ok(value)
+    }
+
+    /// Reads the value, recomputing it if needed, without subscribing the
+    /// current reaction to it.
+    #[must_use]
+    pub fn sample(&self) -> Ref<'_, T> {
+        self.try_sample()
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like [`sample`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// The same as [`try_get`].
+    ///
+    /// [`sample`]: Computed::sample
+    /// [`try_get`]: Computed::try_get
+    pub fn try_sample(&self) -> Result<Ref<'_, T>, Error> {
+        let Inner {
+            engine,
+            value,
+            observer,
+            source,
+        } = &*self.inner;
+        let engine = engine.upgrade().ok_or_else(|| {
+            Error::Disposed {
+                name: Engine::describe_weak(engine, *source),
+            }
+        })?;
+        if engine.is_disposed(*observer) {
+            return Err(Error::Disposed {
+                name: engine.describe(*source),
+            });
+        }
+        // Recompute before tracking, since that's what determines our height.
+        if engine.take_stale(*observer) {
+            engine.run(*observer);
+        }
+        let value = value.try_borrow().map_err(|_| {
+            Error::AlreadyBorrowed {
+                name: engine.describe(*source),
+            }
+        })?;
+        Ok(Ref::map(value, |value| value.as_ref().unwrap()))
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<T: 'static> Computed<T> {
+    /// Returns a future that resolves the next time one of the atoms this
+    /// depends on changes.
+    pub fn changed(&self) -> Changed {
+        // It only hears about changes to what it read last time, so make sure
+        // there was a last time.
+        drop(self.try_sample());
+        Changed::new(&self.inner.engine, self.inner.source)
+    }
+
+    /// Returns a stream of the computed value's values, starting with the
+    /// current one. Changes between polls are coalesced, and the value is only
+    /// recomputed when the stream is polled. The stream ends if the computed
+    /// value's owner disposes it.
+    pub fn stream(&self) -> Values<T>
+    where
+        T: Clone,
+    {
+        let computed = self.clone();
+        Values::new(&self.inner.engine, self.inner.source, move || {
+            computed.try_sample().ok().map(|value| T::clone(&value))
+        })
+    }
+}
+
+impl<T> Clone for Computed<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        // Nobody can read the value anymore, so stop tracking its sources.
+        if let Some(engine) = self.engine.upgrade() {
+            engine.dispose(self.observer);
+            engine.remove_source(self.source);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instance::{Atom, Computed, Engine};
+    use std::{
+        cell::RefCell,
+        panic::{self, AssertUnwindSafe},
+        rc::Rc,
+    };
+
+    #[test]
+    fn lazy_and_memoized() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let calls = Rc::new(RefCell::new(0));
+        let doubled = Computed::new(engine.clone(), {
+            let atom = atom.clone();
+            let calls = calls.clone();
+            move || {
+                *calls.borrow_mut() += 1;
+                *atom.get() * 2
+            }
+        });
+        assert_eq!(*calls.borrow(), 0);
+        assert_eq!(*doubled.get(), 2);
+        assert_eq!(*doubled.get(), 2);
+        assert_eq!(*calls.borrow(), 1);
+
+        atom.set(5);
+        assert_eq!(*calls.borrow(), 1);
+        assert_eq!(*doubled.get(), 10);
+        assert_eq!(*calls.borrow(), 2);
+        drop(engine);
+    }
+
+    #[test]
+    fn tracked_by_reactions() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let doubled = Computed::new(engine.clone(), {
+            let atom = atom.clone();
+            move || *atom.get() * 2
+        });
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let sink = sink.clone();
+            move || {
+                sink.borrow_mut().push(*doubled.get());
+            }
+        });
+        atom.set(2);
+        atom.set(3);
+        assert_eq!(*sink.borrow(), [2, 4, 6]);
+    }
+
+    #[test]
+    fn chained() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let doubled = Computed::new(engine.clone(), {
+            let atom = atom.clone();
+            move || *atom.get() * 2
+        });
+        let plus_one = Computed::new(engine.clone(), move || *doubled.get() + 1);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let sink = sink.clone();
+            move || {
+                sink.borrow_mut().push(*plus_one.get());
+            }
+        });
+        atom.set(10);
+        assert_eq!(*sink.borrow(), [3, 21]);
+    }
+
+    #[test]
+    fn diamond_runs_once_with_consistent_inputs() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let plus_one = Computed::new(engine.clone(), {
+            let atom = atom.clone();
+            move || *atom.get() + 1
+        });
+        let times_two = Computed::new(engine.clone(), {
+            let atom = atom.clone();
+            move || *atom.get() * 2
+        });
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let sink = sink.clone();
+            move || {
+                sink.borrow_mut().push((*plus_one.get(), *times_two.get()));
+            }
+        });
+        atom.set(10);
+        assert_eq!(*sink.borrow(), [(2, 2), (11, 20)]);
+    }
+
+    #[test]
+    fn reaction_reading_atom_and_derived_value_sees_consistent_state() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let doubled = Computed::new(engine.clone(), {
+            let atom = atom.clone();
+            move || *atom.get() * 2
+        });
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || {
+                sink.borrow_mut().push((*atom.get(), *doubled.get()));
+            }
+        });
+        atom.set(5);
+        assert_eq!(*sink.borrow(), [(1, 2), (5, 10)]);
+    }
+
+    #[test]
+    fn recomputes_after_panicking() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let computed = Computed::new(engine.clone(), {
+            let atom = atom.clone();
+            move || {
+                let value = *atom.get();
+                assert_ne!(value, 0);
+                10 / value
+            }
+        });
+        assert_eq!(*computed.get(), 10);
+
+        atom.set(0);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| *computed.get()));
+        assert!(result.is_err());
+
+        atom.set(2);
+        assert_eq!(*computed.get(), 5);
+        drop(engine);
+    }
+
+    #[test]
+    fn try_get_after_owner_disposed() {
+        let engine = Rc::new(Engine::new());
+        let computed = Rc::new(RefCell::new(None));
+        let reaction = engine.react({
+            let engine = engine.clone();
+            let computed = computed.clone();
+            move || {
+                *computed.borrow_mut() = Some(Computed::new(engine.clone(), || 1).named("one"));
+            }
+        });
+        reaction.dispose();
+
+        let computed = computed.borrow_mut().take().unwrap();
+        let error = computed.try_get().err().unwrap();
+        assert_eq!(error.to_string(), "`one` was disposed");
+    }
+
+    #[test]
+    fn read_inside_batch_sees_writes() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let doubled = Computed::new(engine.clone(), {
+            let atom = atom.clone();
+            move || *atom.get() * 2
+        });
+        let plus_one = Computed::new(engine.clone(), {
+            let doubled = doubled.clone();
+            move || *doubled.get() + 1
+        });
+        assert_eq!(*plus_one.get(), 3);
+
+        let batch = engine.batch();
+        atom.set(5);
+        assert_eq!(*doubled.get(), 10);
+        assert_eq!(*plus_one.get(), 11);
+        atom.set(6);
+        assert_eq!(*plus_one.get(), 13);
+        drop(batch);
+        assert_eq!(*plus_one.get(), 13);
+    }
+
+    #[test]
+    fn read_inside_batch_still_notifies_reactions() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let doubled = Computed::new(engine.clone(), {
+            let atom = atom.clone();
+            move || *atom.get() * 2
+        });
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let doubled = doubled.clone();
+            let sink = sink.clone();
+            move || sink.borrow_mut().push(*doubled.get())
+        });
+
+        let batch = engine.batch();
+        atom.set(5);
+        assert_eq!(*doubled.get(), 10);
+        assert_eq!(*sink.borrow(), [2]);
+        drop(batch);
+        assert_eq!(*sink.borrow(), [2, 10]);
+    }
+}
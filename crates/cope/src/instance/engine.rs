@@ -1,9 +1,95 @@
-use std::{cell::RefCell, sync::Arc};
+use crate::{
+    instance::{
+        arena::{Arena, Key},
+        Reaction,
+        Scheduler,
+        Scope,
+        SyncScheduler,
+    },
+    Error,
+};
+use scopeguard::ScopeGuard;
+use std::{
+    any::Any,
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    fmt,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    rc::{Rc, Weak},
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+};
 
-#[derive(Default)]
 pub struct Engine {
-    current_reaction: RefCell<Option<Reaction>>,
-    pub(crate) current_update: RefCell<Option<Update>>,
+    id: EngineId,
+    /// Every atom, computed value and reaction, and the subscriptions between
+    /// them.
+    graph: RefCell<Graph>,
+    /// Every observer that is currently running, innermost last. Only the
+    /// innermost one tracks reads.
+    reaction_stack: RefCell<Vec<Frame>>,
+    current_update: RefCell<Option<Update>>,
+    /// Counts every run of every observer, to tell runs apart while tracking.
+    next_run: Cell<u64>,
+    max_iterations: Cell<u32>,
+    panic_policy: Cell<PanicPolicy>,
+    error_handler: RefCell<Option<Rc<ErrorHandler>>>,
+    scheduler: RefCell<Rc<dyn Scheduler>>,
+    /// Whether `current_update` is waiting for a flush. Nobody owns it until
+    /// then, so the next update to start takes it over instead.
+    pending: Cell<bool>,
+}
+
+/// What happens when a reaction panics.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PanicPolicy {
+    /// The panic unwinds out of whatever caused the reaction to run. This is
+    /// the default.
+    Propagate,
+    /// The panic is caught and passed to the engine's error handler, and
+    /// everything else keeps running. The reaction is marked as errored, and
+    /// tries again the next time one of its dependencies changes.
+    ///
+    /// Computed values can't be caught this way, since they have to produce a
+    /// value. Their panics reach whichever reaction read them.
+    Catch,
+}
+
+type ErrorHandler = dyn Fn(Box<dyn Any + Send>);
+
+/// Identifies an atom or computed value within its engine.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SourceId(Key);
+
+/// Identifies a reaction, computed value or scope within its engine.
+///
+/// An id for something that was disposed stays disposed, even after its slot
+/// is reused.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ObserverId {
+    engine: EngineId,
+    key: Key,
+}
+
+/// Identifies an engine. No two engines in the same process share an id.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct EngineId(u64);
+
+impl EngineId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+thread_local! {
+    /// The engine whose innermost frame on this thread is tracking reads, if
+    /// any. Sources compare this against their own engine, so a reaction can't
+    /// silently subscribe to another engine's atoms.
+    static TRACKING: Cell<Option<EngineId>> = Cell::new(None);
 }
 
 impl Engine {
@@ -12,120 +98,994 @@ impl Engine {
         Self::default()
     }
 
-    pub(crate) fn track(&self, subscriptions: &Arc<RefCell<SubscriptionList>>) {
-        let mut reaction = self.current_reaction.borrow_mut();
-        let reaction = match reaction.as_mut() {
-            Some(reaction) => reaction,
-            None => return,
+    #[must_use]
+    pub fn id(&self) -> EngineId {
+        self.id
+    }
+
+    /// Sets how many times a single reaction may run during one update before
+    /// the engine gives up and reports a cycle. The default is 100.
+    pub fn set_max_iterations(&self, max_iterations: u32) {
+        self.max_iterations.set(max_iterations);
+    }
+
+    pub fn set_panic_policy(&self, policy: PanicPolicy) {
+        self.panic_policy.set(policy);
+    }
+
+    /// Sets the function that receives panics from reactions, when the panic
+    /// policy is [`Catch`]. Without a handler, they are dropped after the panic
+    /// hook has printed them.
+    ///
+    /// [`Catch`]: PanicPolicy::Catch
+    pub fn set_error_handler(&self, handler: impl Fn(Box<dyn Any + Send>) + 'static) {
+        *self.error_handler.borrow_mut() = Some(Rc::new(handler));
+    }
+
+    /// Sets what decides when reactions run. See [`Scheduler`].
+    pub fn set_scheduler(&self, scheduler: impl Scheduler + 'static) {
+        *self.scheduler.borrow_mut() = Rc::new(scheduler);
+    }
+
+    /// Runs every reaction the scheduler has been holding back. Does nothing if
+    /// there aren't any.
+    pub fn flush(&self) {
+        if let Err(error) = self.try_flush() {
+            panic!("{}", error);
+        }
+    }
+
+    /// Like [`flush`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::CycleDetected`] if a reaction ran too many times.
+    ///
+    /// [`flush`]: Engine::flush
+    pub fn try_flush(&self) -> Result<(), Error> {
+        if self.pending.replace(false) {
+            self.try_end_update()
+        } else {
+            Ok(())
+        }
+    }
+
+    fn report(&self, payload: Box<dyn Any + Send>) {
+        // Clone it out first, so the handler is free to replace itself.
+        let handler = self.error_handler.borrow().clone();
+        if let Some(handler) = handler {
+            handler(payload);
+        }
+    }
+
+    pub(crate) fn add_source(&self, type_name: &'static str) -> SourceId {
+        let node = SourceNode {
+            subscribers: Vec::new(),
+            last_run: None,
+            computed: None,
+            name: None,
+            type_name,
         };
-        subscriptions
-            .borrow_mut()
-            .push(reaction.subscriptions.clone());
+        SourceId(self.graph.borrow_mut().sources.insert(node))
     }
 
-    pub fn batch(self: &Arc<Self>) -> Batch {
-        let mut current_update = self.current_update.borrow_mut();
-        let root = current_update.is_none();
-        if !root {
-            return Batch { engine: None };
+    /// Removes a source from the graph, once nothing can read or write it
+    /// anymore.
+    ///
+    /// Reactions that were only subscribed to this source can never run again,
+    /// so they're disposed along with it, unless they own something.
+    pub(crate) fn remove_source(&self, source: SourceId) {
+        let mut garbage = Garbage::default();
+        self.graph.borrow_mut().remove_source(source, &mut garbage);
+        garbage.collect();
+    }
+
+    /// Counts the sources and observers in the graph.
+    #[cfg(test)]
+    pub(crate) fn node_counts(&self) -> (usize, usize) {
+        let graph = self.graph.borrow();
+        (graph.sources.len(), graph.observers.len())
+    }
+
+    pub(crate) fn set_name(&self, source: SourceId, name: Cow<'static, str>) {
+        self.graph.borrow_mut().sources[source.0].name = Some(name);
+    }
+
+    /// Describes a source for error messages.
+    pub(crate) fn describe(&self, source: SourceId) -> String {
+        self.graph.borrow().describe(source)
+    }
+
+    /// Like `describe`, but for atoms and computed values that only hold a
+    /// weak reference, so their engine might be gone.
+    pub(crate) fn describe_weak(engine: &Weak<Self>, source: SourceId) -> String {
+        match engine.upgrade() {
+            Some(engine) => engine.describe(source),
+            None => "a value whose engine was dropped".to_string(),
         }
+    }
+
+    /// Subscribes the current reaction, if any, to a source.
+    ///
+    /// Reading a source while another engine's reaction is running is an
+    /// error, since that reaction would never hear about changes.
+    pub(crate) fn track(&self, source: SourceId) -> Result<(), Error> {
+        match TRACKING.with(Cell::get) {
+            Some(engine) if engine == self.id => {}
+            Some(_) => {
+                return Err(Error::WrongEngine {
+                    name: self.describe(source),
+                });
+            }
+            None => return Ok(()),
+        }
+
+        let reaction_stack = self.reaction_stack.borrow();
+        let frame = match reaction_stack.last() {
+            Some(frame) if frame.tracking => frame,
+            _ => return Ok(()),
+        };
 
+        let mut graph = self.graph.borrow_mut();
+        let graph = &mut *graph;
+        let node = match graph.sources.get_mut(source.0) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        // Atoms have height 0. Computed values have the height of their
+        // observer.
+        let height = match node.computed {
+            Some(computed) => graph.observers.get(computed.key).map_or(0, |c| c.height),
+            None => 0,
+        };
+        let observer = match graph.observers.get_mut(frame.observer.key) {
+            Some(x) if !x.disposed => x,
+            _ => return Ok(()),
+        };
+        if let Kind::Root = observer.kind {
+            return Ok(());
+        }
+        observer.height = observer.height.max(height + 1);
+
+        // This only catches repeated reads when nothing else tracked the source
+        // in between, but a duplicate subscription is harmless.
+        if node.last_run == Some(frame.run) {
+            return Ok(());
+        }
+        node.last_run = Some(frame.run);
+        let source_index = node.subscribers.len();
+        let observer_index = observer.sources.len();
+        node.subscribers.push((frame.observer, observer_index));
+        observer.sources.push((source, source_index));
+        Ok(())
+    }
+
+    pub fn batch(self: &Rc<Self>) -> Batch {
+        let engine = if self.begin_update() {
+            Some(self.clone())
+        } else {
+            None
+        };
+        Batch { engine }
+    }
+
+    /// Starts an update, unless one is already in progress. Returns true if
+    /// this call started it, in which case the caller must call
+    /// `end_update` when it's done.
+    ///
+    /// An update the scheduler is holding back is taken over instead, and put
+    /// back the way it was if the caller aborts.
+    fn begin_update(&self) -> bool {
+        let mut current_update = self.current_update.borrow_mut();
+        if let Some(update) = current_update.as_mut() {
+            if !self.pending.replace(false) {
+                return false;
+            }
+            update.saved = Some(Saved {
+                updates: update.updates.clone(),
+                writes: update.writes.len(),
+            });
+            return true;
+        }
         *current_update = Some(Update::new());
-        Batch {
-            engine: Some(self.clone()),
+        true
+    }
+
+    /// Runs `f` inside an update, starting one first if needed.
+    fn in_update<T>(&self, f: impl FnOnce() -> T) -> T {
+        if !self.begin_update() {
+            return f();
+        }
+        let result = {
+            let _abort = scopeguard::guard_on_unwind((), |()| self.abort_update());
+            f()
+        };
+        self.finish_update();
+        result
+    }
+
+    fn finish_update(&self) {
+        if let Err(error) = self.try_finish_update() {
+            panic!("{}", error);
         }
     }
 
-    pub fn react(&self, mut f: impl FnMut() + 'static) {
-        let mut current_reaction = self.current_reaction.borrow_mut();
-        assert!(current_reaction.is_none());
-        *current_reaction = Some(Reaction::new());
-        drop(current_reaction);
+    /// Ends an update that `begin_update` started, unless the scheduler wants
+    /// to run the queue later, in which case it's left pending until then.
+    fn try_finish_update(&self) -> Result<(), Error> {
+        let ask = {
+            let mut current_update = self.current_update.borrow_mut();
+            let update = current_update.as_mut().unwrap();
+            // Whatever took it over finished, so there's nothing to put back.
+            update.saved = None;
+            if update.updates.is_empty() {
+                None
+            } else {
+                Some(!mem::replace(&mut update.deferred, true))
+            }
+        };
+        match ask {
+            // Nothing to run, so there's nothing to schedule.
+            None => return self.try_end_update(),
+            // The scheduler already knows about this one.
+            Some(false) => {
+                self.pending.set(true);
+                return Ok(());
+            }
+            Some(true) => {}
+        }
+
+        // Mark it pending before asking, in case the scheduler flushes right
+        // away. Clone it out first, so the scheduler is free to replace itself.
+        self.pending.set(true);
+        let scheduler = self.scheduler.borrow().clone();
+        if scheduler.schedule() {
+            self.try_flush()
+        } else {
+            Ok(())
+        }
+    }
+
+    fn try_end_update(&self) -> Result<(), Error> {
+        // If anything panics, drop the rest of the queue so the next update
+        // starts from a clean slate.
+        let abort = scopeguard::guard_on_unwind((), |()| self.abort_update());
+        let result = loop {
+            match self.next() {
+                Ok(Some(head)) => self.invalidate(head),
+                Ok(None) => break Ok(()),
+                Err(error) => break Err(error),
+            }
+        };
+
+        ScopeGuard::into_inner(abort);
+        self.abort_update();
+        result
+    }
+
+    /// Takes the next observer to run, or returns an error describing the cycle
+    /// if it has already run too many times.
+    fn next(&self) -> Result<Option<ObserverId>, Error> {
+        let mut graph = self.graph.borrow_mut();
+        let mut update = self.current_update.borrow_mut();
+        let update = update.as_mut().unwrap();
+        loop {
+            let head = match update.pop() {
+                Some(x) => x,
+                None => return Ok(None),
+            };
+            // Skip anything disposed since it was queued.
+            let node = match graph.observers.get_mut(head.key) {
+                Some(x) => x,
+                None => continue,
+            };
+            node.scheduled = false;
+
+            node.runs += 1;
+            if node.runs == 1 {
+                update.ran.push(head);
+            }
+            let max_iterations = self.max_iterations.get();
+            if node.runs > max_iterations {
+                let write_mark = node.write_mark;
+                let mut names: Vec<String> = Vec::new();
+                for &source in &update.writes[write_mark..] {
+                    let name = graph.describe(source);
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+                return Err(Error::CycleDetected {
+                    max_iterations,
+                    names,
+                });
+            }
+            node.write_mark = update.writes.len();
+            return Ok(Some(head));
+        }
+    }
+
+    /// Ends the update without running anything else in the queue. If it was
+    /// taken over from the scheduler, only what was added since is dropped,
+    /// and the rest is left pending again.
+    fn abort_update(&self) {
+        let mut current_update = self.current_update.borrow_mut();
+        let saved = current_update.as_mut().unwrap().saved.take();
+        let update = match saved {
+            Some(saved) => {
+                let update = current_update.as_mut().unwrap();
+                update.writes.truncate(saved.writes);
+                self.pending.set(true);
+                Update {
+                    updates: mem::replace(&mut update.updates, saved.updates),
+                    ran: mem::take(&mut update.ran),
+                    ..Update::new()
+                }
+            }
+            None => current_update.take().unwrap(),
+        };
+        let mut graph = self.graph.borrow_mut();
+        for &observer in update.updates.values() {
+            if let Some(node) = graph.observers.get_mut(observer.key) {
+                node.scheduled = false;
+            }
+        }
+        for &observer in &update.ran {
+            if let Some(node) = graph.observers.get_mut(observer.key) {
+                node.runs = 0;
+            }
+        }
+        // Anything that was pending before is still queued.
+        if let Some(current_update) = current_update.as_ref() {
+            for &observer in current_update.updates.values() {
+                if let Some(node) = graph.observers.get_mut(observer.key) {
+                    node.scheduled = true;
+                }
+            }
+        }
+    }
 
-        f();
+    /// Records a write to `source`, and queues everything that depends on it.
+    /// If there's no update in progress, the scheduler decides when they run.
+    pub(crate) fn write(&self, source: SourceId) {
+        if let Err(error) = self.try_write(source) {
+            panic!("{}", error);
+        }
+    }
 
-        let mut current_reaction = self.current_reaction.borrow_mut();
-        let reaction = current_reaction.take().unwrap();
-        reaction
-            .subscriptions
+    /// Like `write`, but returns an error if running the queue finds a cycle.
+    /// If an update is already in progress, whoever started it finds out
+    /// instead.
+    pub(crate) fn try_write(&self, source: SourceId) -> Result<(), Error> {
+        let root = self.begin_update();
+        self.current_update
             .borrow_mut()
-            .push(Arc::new(RefCell::new(f)));
+            .as_mut()
+            .unwrap()
+            .writes
+            .push(source);
+        self.schedule(source);
+        if root {
+            self.try_finish_update()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Queues every reaction that depends on `source` to run when the current
+    /// update ends. Each reaction is queued at most once per update.
+    ///
+    /// Computed values in between are marked stale right away instead, so
+    /// reading one before the queue runs already sees the write.
+    fn schedule(&self, source: SourceId) {
+        let mut graph = self.graph.borrow_mut();
+        let graph = &mut *graph;
+        let mut current_update = self.current_update.borrow_mut();
+        let update = current_update.as_mut().expect("not in a batch");
+        let mut changed = vec![source];
+        while let Some(source) = changed.pop() {
+            let node = match graph.sources.get(source.0) {
+                Some(x) => x,
+                None => continue,
+            };
+            for &(observer, _) in &node.subscribers {
+                let observer_node = &mut graph.observers[observer.key];
+                match observer_node.kind {
+                    // Anything downstream of a value that's already stale heard
+                    // about it back when it went stale.
+                    Kind::Computed(source) => {
+                        if !mem::replace(&mut observer_node.stale, true) {
+                            changed.push(source);
+                        }
+                    }
+                    Kind::Reaction | Kind::Root => {
+                        if !mem::replace(&mut observer_node.scheduled, true) {
+                            update.push(observer_node.height, observer);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Responds to a change in one of `observer`'s sources.
+    fn invalidate(&self, observer: ObserverId) {
+        let is_reaction = match self.graph.borrow().observers.get(observer.key) {
+            Some(node) if !node.disposed => matches!(node.kind, Kind::Reaction),
+            _ => return,
+        };
+        if is_reaction {
+            self.run(observer);
+        }
+    }
+
+    pub fn react(self: &Rc<Self>, f: impl FnMut() + 'static) -> Reaction {
+        let observer = self.add_observer(Kind::Reaction, Some(Box::new(f)));
+        self.run(observer);
+        Reaction::new(self.clone(), observer)
+    }
+
+    /// Adds the observer behind a computed value. It starts out stale, and
+    /// `f` only runs when someone reads it.
+    pub(crate) fn add_computed(&self, source: SourceId, f: impl FnMut() + 'static) -> ObserverId {
+        let observer = self.add_observer(Kind::Computed(source), Some(Box::new(f)));
+        let mut graph = self.graph.borrow_mut();
+        graph.observers[observer.key].stale = true;
+        graph.sources[source.0].computed = Some(observer);
+        observer
+    }
+
+    /// Calls `f` every time `source` changes, until the returned reaction is
+    /// disposed. Unlike `react`, `f` doesn't run right away, and the reaction
+    /// isn't owned by whatever is running.
+    #[cfg(feature = "futures")]
+    pub(crate) fn watch(
+        self: &Rc<Self>,
+        source: SourceId,
+        mut f: impl FnMut() + 'static,
+    ) -> Reaction {
+        let engine = Rc::downgrade(self);
+        let mut first = true;
+        let observer = self.insert_observer(
+            Kind::Reaction,
+            Some(Box::new(move || {
+                if let Some(engine) = engine.upgrade() {
+                    // This is the innermost frame, so it can't be the wrong
+                    // engine.
+                    engine.track(source).ok();
+                }
+                if !mem::replace(&mut first, false) {
+                    f();
+                }
+            })),
+        );
+        self.run(observer);
+        Reaction::new(self.clone(), observer)
+    }
+
+    /// Adds an observer, owned by the one currently running, if any.
+    fn add_observer(&self, kind: Kind, f: Option<Box<dyn FnMut()>>) -> ObserverId {
+        let owner = self.reaction_stack.borrow().last().map(|f| f.observer);
+        let observer = self.insert_observer(kind, f);
+        let mut graph = self.graph.borrow_mut();
+        if let Some(owner) = owner.and_then(|o| graph.observers.get_mut(o.key)) {
+            owner.owned.push(observer);
+        }
+        observer
+    }
+
+    fn insert_observer(&self, kind: Kind, f: Option<Box<dyn FnMut()>>) -> ObserverId {
+        let node = ObserverNode::new(kind, f);
+        ObserverId {
+            engine: self.id,
+            key: self.graph.borrow_mut().observers.insert(node),
+        }
+    }
+
+    /// Runs `f` inside a new ownership tree that isn't owned by whatever is
+    /// currently running. Reactions and computed values created by `f` live
+    /// until the returned scope is disposed.
+    pub fn create_root<T>(self: &Rc<Self>, f: impl FnOnce() -> T) -> (Scope, T) {
+        let root = self.insert_observer(Kind::Root, None);
+        let result = self.in_update(|| {
+            // Roots never track anything, so they don't hide whatever is
+            // running outside from other engines.
+            let _frame = self.push_frame(root, 0, false);
+            f()
+        });
+        (Scope::new(self.clone(), root), result)
+    }
+
+    /// Runs `f` without tracking anything it reads. Reactions and computed
+    /// values created inside `f` still track their own reads, and are still
+    /// owned by the current reaction.
+    pub fn untrack<T>(&self, f: impl FnOnce() -> T) -> T {
+        let previous = self.set_tracking(false);
+        // If another engine is running inside this one, it keeps tracking.
+        let outer = TRACKING.with(|current| {
+            let outer = current.get();
+            if outer == Some(self.id) {
+                current.set(None);
+            }
+            outer
+        });
+        let _restore = scopeguard::guard((), |()| {
+            self.set_tracking(previous);
+            TRACKING.with(|current| current.set(outer));
+        });
+        f()
+    }
+
+    fn set_tracking(&self, tracking: bool) -> bool {
+        match self.reaction_stack.borrow_mut().last_mut() {
+            Some(frame) => mem::replace(&mut frame.tracking, tracking),
+            None => false,
+        }
+    }
+
+    /// Pushes a frame onto the reaction stack, and returns a guard that pops it
+    /// again, even if whatever runs in between panics.
+    fn push_frame(&self, observer: ObserverId, run: u64, tracking: bool) -> impl Drop + '_ {
+        self.reaction_stack.borrow_mut().push(Frame {
+            observer,
+            run,
+            tracking,
+        });
+        let engine = if tracking { Some(self.id) } else { None };
+        let outer = TRACKING.with(|current| current.replace(engine));
+        scopeguard::guard((), move |()| {
+            self.reaction_stack.borrow_mut().pop();
+            TRACKING.with(|current| current.set(outer));
+        })
+    }
+
+    /// Whether a reaction or computed value is running, and tracking what it
+    /// reads.
+    #[must_use]
+    pub fn is_tracking(&self) -> bool {
+        self.reaction_stack
+            .borrow()
+            .last()
+            .map_or(false, |frame| frame.tracking)
+    }
+
+    /// Registers `f` to be called before the currently running reaction runs
+    /// again, or when it is disposed. If nothing is running, `f` is never
+    /// called.
+    pub fn on_cleanup(&self, f: impl FnOnce() + 'static) {
+        let owner = match self.reaction_stack.borrow().last() {
+            Some(frame) => frame.observer,
+            None => return,
+        };
+        if let Some(owner) = self.graph.borrow_mut().observers.get_mut(owner.key) {
+            owner.cleanups.push(Box::new(f));
+        }
+    }
+
+    /// Whether `observer` has been disposed. Ids that never belonged to this
+    /// engine count as disposed too.
+    #[must_use]
+    pub fn is_disposed(&self, observer: ObserverId) -> bool {
+        if observer.engine != self.id {
+            return true;
+        }
+        self.graph
+            .borrow()
+            .observers
+            .get(observer.key)
+            .map_or(true, |node| node.disposed)
+    }
+
+    pub(crate) fn is_errored(&self, observer: ObserverId) -> bool {
+        self.graph
+            .borrow()
+            .observers
+            .get(observer.key)
+            .map_or(false, |node| node.errored)
+    }
+
+    /// Disposes a reaction, computed value or scope, along with everything it
+    /// owns. It will never run again. Ids from other engines are ignored.
+    pub fn dispose(&self, observer: ObserverId) {
+        if observer.engine != self.id {
+            return;
+        }
+        let mut garbage = Garbage::default();
+        self.graph.borrow_mut().dispose(observer, &mut garbage);
+        garbage.collect();
+    }
+
+    /// Clears a computed value's stale flag, and returns what it was.
+    pub(crate) fn take_stale(&self, observer: ObserverId) -> bool {
+        match self.graph.borrow_mut().observers.get_mut(observer.key) {
+            Some(node) => mem::replace(&mut node.stale, false),
+            None => false,
+        }
+    }
+
+    /// Runs a reaction, replacing whatever dependencies it had before with the
+    /// ones it reads this time.
+    ///
+    /// This always happens inside an update, so anything the reaction writes
+    /// is queued, and only runs once the reaction is finished.
+    pub(crate) fn run(&self, observer: ObserverId) {
+        self.in_update(|| self.run_in_update(observer));
+    }
+
+    fn run_in_update(&self, observer: ObserverId) {
+        let mut garbage = Garbage::default();
+        {
+            let mut graph = self.graph.borrow_mut();
+            match graph.observers.get(observer.key) {
+                Some(node) if !node.disposed => {}
+                _ => return,
+            }
+            graph.reset(observer, &mut garbage);
+            graph.observers[observer.key].height = 0;
+        }
+        garbage.collect();
+
+        // Take the closure out while it runs, so nothing is borrowed while it
+        // runs, and the reaction can dispose itself.
+        let (f, catch) = {
+            let mut graph = self.graph.borrow_mut();
+            let node = match graph.observers.get_mut(observer.key) {
+                Some(node) if !node.disposed => node,
+                _ => return,
+            };
+            let f = match node.f.take() {
+                Some(f) => f,
+                None => return,
+            };
+            node.running = true;
+            let catch = match (&node.kind, self.panic_policy.get()) {
+                (Kind::Reaction, PanicPolicy::Catch) => true,
+                _ => false,
+            };
+            (f, catch)
+        };
+        // Put everything back the way it was afterwards, even if `f` panics.
+        let mut f = scopeguard::guard(f, |f| {
+            let mut garbage = Garbage::default();
+            {
+                let mut graph = self.graph.borrow_mut();
+                let node = &mut graph.observers[observer.key];
+                node.running = false;
+                if node.disposed {
+                    // Drop whatever it subscribed to or registered on the way
+                    // out.
+                    graph.reset(observer, &mut garbage);
+                    graph.observers.remove(observer.key);
+                    garbage.closures.push(f);
+                } else {
+                    node.f = Some(f);
+                    if thread::panicking() {
+                        node.errored = true;
+                        // Whatever was cached is out of date, so try again next
+                        // time.
+                        if let Kind::Computed(_) = node.kind {
+                            node.stale = true;
+                        }
+                    }
+                }
+            }
+            garbage.collect();
+        });
+
+        let run = self.next_run.get();
+        self.next_run.set(run + 1);
+        let frame = self.push_frame(observer, run, true);
+        let call = &mut *f;
+        let result = if catch {
+            panic::catch_unwind(AssertUnwindSafe(call))
+        } else {
+            call();
+            Ok(())
+        };
+        drop(frame);
+        drop(f);
+
+        let errored = result.is_err();
+        if let Some(node) = self.graph.borrow_mut().observers.get_mut(observer.key) {
+            node.errored = errored;
+        }
+        if let Err(payload) = result {
+            self.report(payload);
+        }
     }
 }
 
-fn slow_pop_front<T>(xs: &mut Vec<T>) -> Option<T> {
-    if xs.is_empty() {
-        None
-    } else {
-        Some(xs.remove(0))
+impl Default for Engine {
+    fn default() -> Self {
+        Self {
+            id: EngineId::next(),
+            graph: RefCell::new(Graph {
+                sources: Arena::new(),
+                observers: Arena::new(),
+            }),
+            reaction_stack: RefCell::new(Vec::new()),
+            current_update: RefCell::new(None),
+            next_run: Cell::new(0),
+            max_iterations: Cell::new(100),
+            panic_policy: Cell::new(PanicPolicy::Propagate),
+            error_handler: RefCell::new(None),
+            scheduler: RefCell::new(Rc::new(SyncScheduler)),
+            pending: Cell::new(false),
+        }
     }
 }
 
-type SubscriptionList = Vec<Arc<RefCell<Vec<Subscription>>>>;
-type Subscription = Arc<RefCell<dyn FnMut()>>;
+struct Frame {
+    observer: ObserverId,
+    /// Which run of `observer` this is.
+    run: u64,
+    /// Whether reads are currently being tracked. This is false inside
+    /// `untrack`.
+    tracking: bool,
+}
 
-struct Reaction {
-    subscriptions: Arc<RefCell<Vec<Subscription>>>,
+/// The dependency graph.
+///
+/// Each subscription is stored on both ends: in the source's `subscribers`
+/// and in the observer's `sources`, and each entry records its index in the
+/// other list. That way either end can remove it in O(1).
+struct Graph {
+    sources: Arena<SourceNode>,
+    observers: Arena<ObserverNode>,
 }
 
-impl Reaction {
-    pub fn new() -> Self {
-        Reaction {
-            subscriptions: Arc::new(RefCell::new(Vec::new())),
+impl Graph {
+    /// Removes every subscription `observer` has.
+    fn unsubscribe(&mut self, observer: ObserverId) {
+        // Work backwards, so that removing an entry never moves one of this
+        // observer's entries that has already been removed.
+        while let Some((source, source_index)) = self.observers[observer.key].sources.pop() {
+            let subscribers = &mut self.sources[source.0].subscribers;
+            subscribers.swap_remove(source_index);
+            if let Some(&(moved, moved_index)) = subscribers.get(source_index) {
+                self.observers[moved.key].sources[moved_index].1 = source_index;
+            }
+        }
+    }
+
+    fn remove_source(&mut self, source: SourceId, garbage: &mut Garbage) {
+        let mut orphans = Vec::new();
+        while let Some((observer, observer_index)) = self.sources[source.0].subscribers.pop() {
+            let node = &mut self.observers[observer.key];
+            node.sources.swap_remove(observer_index);
+            if let Some(&(moved, moved_index)) = node.sources.get(observer_index) {
+                self.sources[moved.0].subscribers[moved_index].1 = observer_index;
+            } else if node.is_orphan() {
+                orphans.push(observer);
+            }
+        }
+        self.sources.remove(source.0);
+        for observer in orphans {
+            self.dispose(observer, garbage);
+        }
+    }
+
+    /// Gets an observer ready to run again: unsubscribes it, disposes whatever
+    /// it owns, and takes its cleanups.
+    fn reset(&mut self, observer: ObserverId, garbage: &mut Garbage) {
+        self.unsubscribe(observer);
+        let node = &mut self.observers[observer.key];
+        let owned = mem::take(&mut node.owned);
+        let cleanups = mem::take(&mut node.cleanups);
+        for child in owned {
+            self.dispose(child, garbage);
+        }
+        garbage.cleanups.extend(cleanups);
+    }
+
+    fn dispose(&mut self, observer: ObserverId, garbage: &mut Garbage) {
+        match self.observers.get_mut(observer.key) {
+            Some(node) if !node.disposed => node.disposed = true,
+            _ => return,
+        }
+        self.reset(observer, garbage);
+        // If the closure is running right now, `Engine::run` removes it
+        // instead once it returns.
+        if !self.observers[observer.key].running {
+            let node = self.observers.remove(observer.key).unwrap();
+            garbage.closures.extend(node.f);
+        }
+    }
+
+    fn describe(&self, source: SourceId) -> String {
+        match self.sources.get(source.0) {
+            Some(node) => node.to_string(),
+            None => "a dropped atom".to_string(),
+        }
+    }
+}
+
+/// Closures taken out of the graph. Calling or dropping them runs arbitrary
+/// code, which might use the engine, so that has to wait until the graph isn't
+/// borrowed anymore.
+#[derive(Default)]
+struct Garbage {
+    closures: Vec<Box<dyn FnMut()>>,
+    cleanups: Vec<Box<dyn FnOnce()>>,
+}
+
+impl Garbage {
+    fn collect(self) {
+        for cleanup in self.cleanups {
+            cleanup();
+        }
+        drop(self.closures);
+    }
+}
+
+/// An atom or computed value.
+struct SourceNode {
+    /// Every observer that read this during its last run, along with this
+    /// source's index in its `sources`.
+    subscribers: Vec<(ObserverId, usize)>,
+    /// The last run that subscribed to this.
+    last_run: Option<u64>,
+    /// For computed values, the observer that computes it.
+    computed: Option<ObserverId>,
+    name: Option<Cow<'static, str>>,
+    type_name: &'static str,
+}
+
+impl fmt::Display for SourceNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "`{}`", name),
+            None => write!(f, "an unnamed `{}`", self.type_name),
+        }
+    }
+}
+
+enum Kind {
+    Reaction,
+    /// A computed value, along with the source its readers subscribe to.
+    Computed(SourceId),
+    /// The root of an ownership tree. It owns things, but never runs or tracks
+    /// anything itself.
+    Root,
+}
+
+#[allow(clippy::struct_excessive_bools)]
+struct ObserverNode {
+    kind: Kind,
+    /// `None` while the closure is running, and always for roots.
+    f: Option<Box<dyn FnMut()>>,
+    /// Every atom or computed value read during the last run, along with this
+    /// observer's index in its `subscribers`.
+    sources: Vec<(SourceId, usize)>,
+    /// Reactions and computed values created during the last run. They're
+    /// disposed before the next run, or when this one is disposed.
+    owned: Vec<ObserverId>,
+    /// Callbacks registered with `on_cleanup` during the last run.
+    cleanups: Vec<Box<dyn FnOnce()>>,
+    /// Whether this is already waiting in the current update's queue.
+    scheduled: bool,
+    /// For computed values, whether the cached value needs to be recomputed.
+    stale: bool,
+    /// One more than the height of the highest source read during the last run.
+    /// Queued reactions run in order of increasing height.
+    height: u32,
+    /// Whether the last run panicked.
+    errored: bool,
+    /// How many times this has been invalidated during the current update.
+    runs: u32,
+    /// The length of the current update's write log when this last ran.
+    write_mark: usize,
+    /// Whether the closure is running right now.
+    running: bool,
+    /// Set if this is disposed while it's running. It's removed from the graph
+    /// once the run finishes.
+    disposed: bool,
+}
+
+impl ObserverNode {
+    /// Whether this is a reaction that nothing can ever trigger again.
+    fn is_orphan(&self) -> bool {
+        match self.kind {
+            Kind::Reaction => self.sources.is_empty() && self.owned.is_empty() && !self.running,
+            Kind::Computed(_) | Kind::Root => false,
+        }
+    }
+
+    fn new(kind: Kind, f: Option<Box<dyn FnMut()>>) -> Self {
+        Self {
+            kind,
+            f,
+            sources: Vec::new(),
+            owned: Vec::new(),
+            cleanups: Vec::new(),
+            scheduled: false,
+            stale: false,
+            height: 0,
+            errored: false,
+            runs: 0,
+            write_mark: 0,
+            running: false,
+            disposed: false,
         }
     }
 }
 
-pub(crate) struct Update {
-    updates: Vec<Box<dyn FnOnce()>>,
+struct Update {
+    /// Keyed by height, then by the order they were queued in.
+    updates: BTreeMap<(u32, u64), ObserverId>,
+    next_sequence: u64,
+    /// Every observer that has run so far, so their run counts can be reset.
+    ran: Vec<ObserverId>,
+    /// Every atom written so far, in order.
+    writes: Vec<SourceId>,
+    /// Whether the scheduler has been asked about this update yet.
+    deferred: bool,
+    /// What was pending when a batch or write took the update over from the
+    /// scheduler, so that aborting only drops what was added since.
+    saved: Option<Saved>,
+}
+
+struct Saved {
+    updates: BTreeMap<(u32, u64), ObserverId>,
+    writes: usize,
 }
 
 impl Update {
-    pub fn new() -> Self {
+    fn new() -> Self {
         Update {
-            updates: Vec::new(),
+            updates: BTreeMap::new(),
+            next_sequence: 0,
+            ran: Vec::new(),
+            writes: Vec::new(),
+            deferred: false,
+            saved: None,
         }
     }
+
+    fn push(&mut self, height: u32, observer: ObserverId) {
+        let key = (height, self.next_sequence);
+        self.next_sequence += 1;
+        self.updates.insert(key, observer);
+    }
+
+    fn pop(&mut self) -> Option<ObserverId> {
+        let key = *self.updates.keys().next()?;
+        self.updates.remove(&key)
+    }
 }
 
 #[must_use]
 pub struct Batch {
-    engine: Option<Arc<Engine>>,
+    engine: Option<Rc<Engine>>,
 }
 
 impl Drop for Batch {
     fn drop(&mut self) {
-        let engine = match &mut self.engine {
-            Some(x) => x,
-            None => return,
-        };
-
-        loop {
-            let head = {
-                let mut update = engine.current_update.borrow_mut();
-                slow_pop_front(&mut update.as_mut().unwrap().updates)
-            };
-            let head = match head {
-                Some(x) => x,
-                None => break,
-            };
-            head();
+        if let Some(engine) = &self.engine {
+            // If the batch is being dropped by a panic, don't run anything.
+            if thread::panicking() {
+                engine.abort_update();
+            } else {
+                engine.finish_update();
+            }
         }
-
-        engine.current_update.borrow_mut().take().unwrap();
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::instance::{Atom, Engine};
-    use std::{cell::RefCell, sync::Arc};
+    use crate::instance::{Atom, Engine, PanicPolicy};
+    use std::{
+        cell::RefCell,
+        panic::{self, AssertUnwindSafe},
+        rc::Rc,
+    };
 
     #[test]
     fn react_simple() {
-        let engine = Arc::new(Engine::new());
+        let engine = Rc::new(Engine::new());
         let atom = Atom::new(engine.clone(), 1);
-        let sink = Arc::new(RefCell::new(Vec::new()));
+        let sink = Rc::new(RefCell::new(Vec::new()));
         engine.react({
             let atom = atom.clone();
             let sink = sink.clone();
@@ -137,4 +1097,457 @@ mod tests {
         atom.set(2);
         assert_eq!(*sink.borrow(), [1, 2]);
     }
+
+    #[test]
+    fn react_retracks_dependencies() {
+        let engine = Rc::new(Engine::new());
+        let flag = Atom::new(engine.clone(), true);
+        let a = Atom::new(engine.clone(), "a");
+        let b = Atom::new(engine.clone(), "b");
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let flag = flag.clone();
+            let a = a.clone();
+            let b = b.clone();
+            let sink = sink.clone();
+            move || {
+                let value = if *flag.get() { *a.get() } else { *b.get() };
+                sink.borrow_mut().push(value);
+            }
+        });
+        assert_eq!(*sink.borrow(), ["a"]);
+        b.set("b2");
+        assert_eq!(*sink.borrow(), ["a"]);
+        flag.set(false);
+        assert_eq!(*sink.borrow(), ["a", "b2"]);
+        b.set("b3");
+        assert_eq!(*sink.borrow(), ["a", "b2", "b3"]);
+        a.set("a2");
+        assert_eq!(*sink.borrow(), ["a", "b2", "b3"]);
+    }
+
+    #[test]
+    fn batch_defers_and_dedups() {
+        let engine = Rc::new(Engine::new());
+        let a = Atom::new(engine.clone(), 1);
+        let b = Atom::new(engine.clone(), 10);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let a = a.clone();
+            let b = b.clone();
+            let sink = sink.clone();
+            move || {
+                sink.borrow_mut().push(*a.get() + *b.get());
+            }
+        });
+        assert_eq!(*sink.borrow(), [11]);
+
+        let batch = engine.batch();
+        a.set(2);
+        b.set(20);
+        a.set(3);
+        assert_eq!(*sink.borrow(), [11]);
+        drop(batch);
+        assert_eq!(*sink.borrow(), [11, 23]);
+    }
+
+    #[test]
+    fn batch_runs_reactions_in_order() {
+        let engine = Rc::new(Engine::new());
+        let a = Atom::new(engine.clone(), 0);
+        let b = Atom::new(engine.clone(), 0);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        for (name, atom) in &[("a", &a), ("b", &b)] {
+            engine.react({
+                let name = *name;
+                let atom = (*atom).clone();
+                let sink = sink.clone();
+                move || {
+                    drop(atom.get());
+                    sink.borrow_mut().push(name);
+                }
+            });
+        }
+        sink.borrow_mut().clear();
+
+        let batch = engine.batch();
+        b.set(1);
+        a.set(1);
+        b.set(2);
+        drop(batch);
+        assert_eq!(*sink.borrow(), ["b", "a"]);
+    }
+
+    #[test]
+    fn react_inside_react() {
+        let engine = Rc::new(Engine::new());
+        let outer_atom = Atom::new(engine.clone(), 1);
+        let inner_atom = Atom::new(engine.clone(), 10);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let engine = engine.clone();
+            let outer_atom = outer_atom.clone();
+            let inner_atom = inner_atom.clone();
+            let sink = sink.clone();
+            move || {
+                let outer = *outer_atom.get();
+                sink.borrow_mut().push(("outer", outer));
+                engine.react({
+                    let inner_atom = inner_atom.clone();
+                    let sink = sink.clone();
+                    move || {
+                        sink.borrow_mut().push(("inner", *inner_atom.get()));
+                    }
+                });
+                // Reads after the inner reaction is created still belong to the
+                // outer one.
+                drop(outer_atom.get());
+            }
+        });
+        assert_eq!(*sink.borrow(), [("outer", 1), ("inner", 10)]);
+        sink.borrow_mut().clear();
+
+        // The inner reaction's read didn't leak into the outer reaction.
+        inner_atom.set(11);
+        assert_eq!(*sink.borrow(), [("inner", 11)]);
+        sink.borrow_mut().clear();
+
+        outer_atom.set(2);
+        assert_eq!(*sink.borrow(), [("outer", 2), ("inner", 11)]);
+    }
+
+    #[test]
+    fn on_cleanup() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let reaction = engine.react({
+            let engine = engine.clone();
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || {
+                let value = *atom.get();
+                sink.borrow_mut().push(format!("run {}", value));
+                engine.on_cleanup({
+                    let sink = sink.clone();
+                    move || sink.borrow_mut().push(format!("clean up {}", value))
+                });
+            }
+        });
+        atom.set(2);
+        reaction.dispose();
+        atom.set(3);
+        assert_eq!(*sink.borrow(), [
+            "run 1",
+            "clean up 1",
+            "run 2",
+            "clean up 2",
+        ]);
+    }
+
+    #[test]
+    fn untrack() {
+        let engine = Rc::new(Engine::new());
+        let tracked = Atom::new(engine.clone(), 1);
+        let untracked = Atom::new(engine.clone(), 10);
+        let sampled = Atom::new(engine.clone(), 100);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let engine = engine.clone();
+            let tracked = tracked.clone();
+            let untracked = untracked.clone();
+            let sampled = sampled.clone();
+            let sink = sink.clone();
+            move || {
+                let a = *tracked.get();
+                let b = engine.untrack(|| *untracked.get());
+                let c = *sampled.sample();
+                sink.borrow_mut().push(a + b + c);
+            }
+        });
+        untracked.set(20);
+        sampled.set(200);
+        assert_eq!(*sink.borrow(), [111]);
+        tracked.set(2);
+        assert_eq!(*sink.borrow(), [111, 222]);
+    }
+
+    #[test]
+    fn untrack_does_not_affect_inner_reactions() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.untrack(|| {
+            engine.react({
+                let atom = atom.clone();
+                let sink = sink.clone();
+                move || {
+                    sink.borrow_mut().push(*atom.get());
+                }
+            })
+        });
+        atom.set(2);
+        assert_eq!(*sink.borrow(), [1, 2]);
+    }
+
+    #[test]
+    fn writes_inside_reactions_are_deferred() {
+        let engine = Rc::new(Engine::new());
+        let a = Atom::new(engine.clone(), 0);
+        let b = Atom::new(engine.clone(), 0);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let a = a.clone();
+            let b = b.clone();
+            let sink = sink.clone();
+            move || {
+                b.set(*a.get());
+                sink.borrow_mut().push("wrote b");
+            }
+        });
+        engine.react({
+            let sink = sink.clone();
+            move || {
+                drop(b.get());
+                sink.borrow_mut().push("read b");
+            }
+        });
+        sink.borrow_mut().clear();
+
+        a.set(1);
+        assert_eq!(*sink.borrow(), ["wrote b", "read b"]);
+    }
+
+    #[test]
+    fn reaction_writing_its_own_dependency_converges() {
+        let engine = Rc::new(Engine::new());
+        let count = Atom::new(engine.clone(), 0);
+        let runs = Rc::new(RefCell::new(0));
+        engine.react({
+            let count = count.clone();
+            let runs = runs.clone();
+            move || {
+                *runs.borrow_mut() += 1;
+                let n = *count.get();
+                if n < 5 {
+                    count.set(n + 1);
+                }
+            }
+        });
+        assert_eq!(*count.sample(), 5);
+        assert_eq!(*runs.borrow(), 6);
+
+        count.set(0);
+        assert_eq!(*count.sample(), 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "Atoms written since it last ran: `pong`, `ping`")]
+    fn cycle_is_reported() {
+        let engine = Rc::new(Engine::new());
+        let ping = Atom::new(engine.clone(), 0).named("ping");
+        let pong = Atom::new(engine.clone(), 0).named("pong");
+        engine.set_max_iterations(10);
+        engine.react({
+            let ping = ping.clone();
+            let pong = pong.clone();
+            move || pong.set(*ping.get() + 1)
+        });
+        engine.react(move || ping.set(*pong.get() + 1));
+    }
+
+    #[test]
+    fn engine_recovers_after_cycle() {
+        let engine = Rc::new(Engine::new());
+        let count = Atom::new(engine.clone(), 0);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            engine.react({
+                let count = count.clone();
+                move || {
+                    let n = *count.get();
+                    count.set(n + 1);
+                }
+            });
+        }));
+        assert!(result.is_err());
+
+        let seen = Rc::new(RefCell::new(0));
+        let other = Atom::new(engine.clone(), 0);
+        engine.react({
+            let other = other.clone();
+            let seen = seen.clone();
+            move || *seen.borrow_mut() = *other.get()
+        });
+        other.set(3);
+        assert_eq!(*seen.borrow(), 3);
+    }
+
+    #[test]
+    fn engine_recovers_after_reaction_panics() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 0);
+        let reaction = engine.react({
+            let atom = atom.clone();
+            move || assert_ne!(*atom.get(), 1)
+        });
+        let result = panic::catch_unwind(AssertUnwindSafe(|| atom.set(1)));
+        assert!(result.is_err());
+        assert!(reaction.is_errored());
+
+        atom.set(2);
+        assert!(!reaction.is_errored());
+
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || sink.borrow_mut().push(*atom.get())
+        });
+        atom.set(3);
+        assert_eq!(*sink.borrow(), [2, 3]);
+    }
+
+    #[test]
+    fn catch_reports_panics_to_error_handler() {
+        let engine = Rc::new(Engine::new());
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        engine.set_panic_policy(PanicPolicy::Catch);
+        engine.set_error_handler({
+            let errors = errors.clone();
+            move |payload| {
+                let message = *payload.downcast_ref::<&str>().unwrap();
+                errors.borrow_mut().push(message);
+            }
+        });
+
+        let atom = Atom::new(engine.clone(), 0);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let reaction = engine.react({
+            let atom = atom.clone();
+            move || {
+                if *atom.get() == 1 {
+                    panic!("boom");
+                }
+            }
+        });
+        engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || sink.borrow_mut().push(*atom.get())
+        });
+
+        atom.set(1);
+        assert!(reaction.is_errored());
+        assert_eq!(*errors.borrow(), ["boom"]);
+        assert_eq!(*sink.borrow(), [0, 1]);
+
+        atom.set(2);
+        assert!(!reaction.is_errored());
+    }
+
+    #[test]
+    fn panic_inside_batch_drops_queued_reactions() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 0);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || sink.borrow_mut().push(*atom.get())
+        });
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _batch = engine.batch();
+            atom.set(1);
+            panic!("boom");
+        }));
+        assert!(result.is_err());
+        assert_eq!(*sink.borrow(), [0]);
+
+        atom.set(2);
+        assert_eq!(*sink.borrow(), [0, 2]);
+    }
+
+    #[test]
+    fn dispose_by_id() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let id = engine
+            .react({
+                let atom = atom.clone();
+                let sink = sink.clone();
+                move || sink.borrow_mut().push(*atom.get())
+            })
+            .id();
+        assert!(!engine.is_disposed(id));
+        engine.dispose(id);
+        assert!(engine.is_disposed(id));
+        atom.set(2);
+        assert_eq!(*sink.borrow(), [1]);
+
+        // The slot gets reused, but the old id still refers to nothing.
+        let other = engine.react(|| {});
+        assert_ne!(other.id(), id);
+        assert!(engine.is_disposed(id));
+    }
+
+    #[test]
+    fn engines_have_distinct_ids() {
+        assert_ne!(Engine::new().id(), Engine::new().id());
+    }
+
+    #[test]
+    fn reading_another_engines_atom_is_reported() {
+        let a = Rc::new(Engine::new());
+        let b = Rc::new(Engine::new());
+        let atom = Atom::new(b.clone(), 1).named("foreign");
+        let result = Rc::new(RefCell::new(None));
+        a.react({
+            let atom = atom.clone();
+            let result = result.clone();
+            move || *result.borrow_mut() = Some(atom.try_get().map(|x| *x))
+        });
+        assert_eq!(
+            *result.borrow(),
+            Some(Err(crate::Error::WrongEngine {
+                name: "`foreign`".to_string(),
+            })),
+        );
+
+        // Untracked reads are fine, since nothing subscribes.
+        a.react({
+            let a = a.clone();
+            let result = result.clone();
+            move || *result.borrow_mut() = Some(a.untrack(|| atom.try_get().map(|x| *x)))
+        });
+        assert_eq!(*result.borrow(), Some(Ok(1)));
+        drop(b);
+    }
+
+    #[test]
+    fn writes_to_another_engine_run_its_reactions() {
+        let a = Rc::new(Engine::new());
+        let b = Rc::new(Engine::new());
+        let source = Atom::new(a.clone(), 1);
+        let mirror = Atom::new(b.clone(), 0);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        b.react({
+            let mirror = mirror.clone();
+            let sink = sink.clone();
+            move || sink.borrow_mut().push(*mirror.get())
+        });
+        a.react({
+            let source = source.clone();
+            move || mirror.set(*source.get())
+        });
+        source.set(2);
+        assert_eq!(*sink.borrow(), [0, 1, 2]);
+
+        // Ids from one engine mean nothing to another.
+        let reaction = b.react(|| {});
+        assert!(a.is_disposed(reaction.id()));
+        a.dispose(reaction.id());
+        assert!(!b.is_disposed(reaction.id()));
+    }
 }
@@ -1,7 +1,22 @@
+#[cfg(feature = "futures")]
+pub use self::changes::{Changed, Values};
 pub use self::{
-    atom::{Atom, AtomMut},
-    engine::{Batch, Engine},
+    atom::{Atom, AtomMut, WeakAtom},
+    computed::Computed,
+    engine::{Batch, Engine, EngineId, ObserverId, PanicPolicy, SourceId},
+    reaction::{Reaction, ReactionGuard},
+    resource::{Resource, ResourceState},
+    scheduler::{ExternalScheduler, ManualScheduler, Scheduler, SyncScheduler},
+    scope::Scope,
 };
 
+mod arena;
 mod atom;
+#[cfg(feature = "futures")]
+mod changes;
+mod computed;
 mod engine;
+mod reaction;
+mod resource;
+mod scheduler;
+mod scope;
@@ -0,0 +1,177 @@
+use crate::instance::{Engine, ObserverId};
+use std::rc::Rc;
+
+/// A handle to a reaction created by [`Engine::react`].
+///
+/// Dropping the handle leaves the reaction running. Call [`dispose`] to stop
+/// it, or convert it with [`into_guard`] to stop it when the guard is dropped.
+///
+/// The handle keeps the engine alive, but atoms and computed values don't, so
+/// a reaction that captures them doesn't keep itself alive. Once every handle
+/// to the engine is dropped, the engine is freed along with every reaction and
+/// whatever their closures captured.
+///
+/// [`Engine::react`]: crate::instance::Engine::react
+/// [`dispose`]: Reaction::dispose
+/// [`into_guard`]: Reaction::into_guard
+#[derive(Clone)]
+pub struct Reaction {
+    engine: Rc<Engine>,
+    observer: ObserverId,
+}
+
+impl Reaction {
+    pub(crate) fn new(engine: Rc<Engine>, observer: ObserverId) -> Self {
+        Self { engine, observer }
+    }
+
+    #[must_use]
+    pub fn id(&self) -> ObserverId {
+        self.observer
+    }
+
+    #[must_use]
+    pub fn is_disposed(&self) -> bool {
+        self.engine.is_disposed(self.observer)
+    }
+
+    /// Whether the reaction panicked the last time it ran.
+    #[must_use]
+    pub fn is_errored(&self) -> bool {
+        self.engine.is_errored(self.observer)
+    }
+
+    /// Unsubscribes the reaction from every atom it depends on and drops its
+    /// closure. It will never run again.
+    pub fn dispose(&self) {
+        self.engine.dispose(self.observer);
+    }
+
+    pub fn into_guard(self) -> ReactionGuard {
+        ReactionGuard { reaction: self }
+    }
+}
+
+/// Disposes a reaction when dropped.
+#[must_use]
+#[allow(clippy::module_name_repetitions)]
+pub struct ReactionGuard {
+    reaction: Reaction,
+}
+
+impl Drop for ReactionGuard {
+    fn drop(&mut self) {
+        self.reaction.dispose();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instance::{Atom, Engine};
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn dispose() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let reaction = engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || {
+                sink.borrow_mut().push(*atom.get());
+            }
+        });
+        atom.set(2);
+        reaction.dispose();
+        assert!(reaction.is_disposed());
+        atom.set(3);
+        assert_eq!(*sink.borrow(), [1, 2]);
+    }
+
+    #[test]
+    fn dispose_frees_closure() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let captured = Rc::new(());
+        let reaction = engine.react({
+            let captured = captured.clone();
+            move || {
+                let _ = (*atom.get(), &captured);
+            }
+        });
+        assert_eq!(Rc::strong_count(&captured), 2);
+        reaction.dispose();
+        assert_eq!(Rc::strong_count(&captured), 1);
+    }
+
+    #[test]
+    fn dropping_every_handle_frees_captured_atoms_and_engine() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let captured = Rc::new(());
+        let reaction = engine.react({
+            let atom = atom.clone();
+            let captured = captured.clone();
+            move || {
+                let _ = (*atom.get(), &captured);
+            }
+        });
+        atom.set(2);
+        assert_eq!(engine.node_counts(), (1, 1));
+
+        let weak = Rc::downgrade(&engine);
+        drop(engine);
+        drop(atom);
+        // The reaction handle keeps the engine alive, but nothing else does.
+        assert!(weak.upgrade().is_some());
+        drop(reaction);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(Rc::strong_count(&captured), 1);
+    }
+
+    #[test]
+    fn guard_disposes_on_drop() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let guard = engine
+            .react({
+                let atom = atom.clone();
+                let sink = sink.clone();
+                move || {
+                    sink.borrow_mut().push(*atom.get());
+                }
+            })
+            .into_guard();
+        atom.set(2);
+        drop(guard);
+        atom.set(3);
+        assert_eq!(*sink.borrow(), [1, 2]);
+    }
+
+    #[test]
+    fn dispose_from_inside_reaction() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let handle = Rc::new(RefCell::new(None::<crate::instance::Reaction>));
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let reaction = engine.react({
+            let atom = atom.clone();
+            let handle = handle.clone();
+            let sink = sink.clone();
+            move || {
+                let value = *atom.get();
+                sink.borrow_mut().push(value);
+                if value == 2 {
+                    handle.borrow().as_ref().unwrap().dispose();
+                }
+            }
+        });
+        *handle.borrow_mut() = Some(reaction.clone());
+        atom.set(2);
+        atom.set(3);
+        assert_eq!(*sink.borrow(), [1, 2]);
+        assert!(reaction.is_disposed());
+    }
+}
@@ -0,0 +1,320 @@
+use crate::instance::{Atom, ReactionGuard};
+use std::{
+    cell::{Ref, RefCell},
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+/// Where a [`Resource`] is at.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[allow(clippy::module_name_repetitions)]
+pub enum ResourceState<T, E> {
+    /// A fetch is in flight.
+    Loading,
+    Ready(T),
+    Failed(E),
+}
+
+/// The result of an async fetch, kept up to date with the atom it was fetched
+/// from.
+///
+/// Every time the source atom changes, the resource goes back to
+/// [`Loading`], cancels the fetch in flight, if any, and starts a new one.
+/// Dropping the resource cancels the fetch in flight too.
+///
+/// [`Loading`]: ResourceState::Loading
+pub struct Resource<T, E> {
+    state: Atom<ResourceState<T, E>>,
+    _reaction: ReactionGuard,
+}
+
+impl<T: 'static, E: 'static> Resource<T, E> {
+    /// Creates a resource that calls `fetch` with the value of `source` now,
+    /// and again every time it changes. It lives on the same engine as
+    /// `source`.
+    ///
+    /// `spawn` hands each fetch to whatever executor the app uses, for example
+    /// `tokio::task::spawn_local` or `wasm_bindgen_futures::spawn_local`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the engine `source` belongs to has been dropped.
+    pub fn new<S: 'static, Fut>(
+        source: &Atom<S>,
+        mut fetch: impl FnMut(&S) -> Fut + 'static,
+        spawn: impl Fn(Pin<Box<dyn Future<Output = ()>>>) + 'static,
+    ) -> Self
+    where
+        Fut: Future<Output = Result<T, E>> + 'static,
+    {
+        let engine = source
+            .engine()
+            .expect("the source atom's engine was dropped");
+        let state = Atom::new(engine.clone(), ResourceState::Loading);
+        let reaction = engine.react({
+            let source = source.clone();
+            let state = state.clone();
+            let mut in_flight = InFlight(None);
+            move || {
+                let future = fetch(&source.get());
+                in_flight.cancel();
+                let handle = Rc::new(RefCell::new(Handle::default()));
+                in_flight.0 = Some(handle.clone());
+
+                if !matches!(*state.sample(), ResourceState::Loading) {
+                    state.replace(ResourceState::Loading);
+                }
+                spawn(Box::pin(Fetch {
+                    future: Box::pin(future),
+                    handle,
+                    state: state.clone(),
+                }));
+            }
+        });
+        Self {
+            state,
+            _reaction: reaction.into_guard(),
+        }
+    }
+
+    /// Reads the state, and subscribes the current reaction to it.
+    #[must_use]
+    pub fn state(&self) -> Ref<'_, ResourceState<T, E>> {
+        self.state.get()
+    }
+
+    /// Reads the state without subscribing the current reaction to it.
+    #[must_use]
+    pub fn sample(&self) -> Ref<'_, ResourceState<T, E>> {
+        self.state.sample()
+    }
+}
+
+/// Shared between a fetch in flight and the reaction that started it.
+#[derive(Default)]
+struct Handle {
+    cancelled: bool,
+    waker: Option<Waker>,
+}
+
+/// The reaction's handle to its fetch in flight. Dropping it, which happens
+/// when the reaction is disposed, cancels the fetch.
+struct InFlight(Option<Rc<RefCell<Handle>>>);
+
+impl InFlight {
+    fn cancel(&mut self) {
+        let handle = match self.0.take() {
+            Some(handle) => handle,
+            None => return,
+        };
+        let waker = {
+            let mut handle = handle.borrow_mut();
+            handle.cancelled = true;
+            handle.waker.take()
+        };
+        // Wake the task so it notices, and drops the fetch right away instead
+        // of whenever the fetch would have woken it.
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Drives a fetch, and writes its result to the state unless it's cancelled
+/// first.
+struct Fetch<F, T, E> {
+    future: Pin<Box<F>>,
+    handle: Rc<RefCell<Handle>>,
+    state: Atom<ResourceState<T, E>>,
+}
+
+impl<F, T: 'static, E: 'static> Future for Fetch<F, T, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        {
+            let mut handle = self.handle.borrow_mut();
+            if handle.cancelled {
+                return Poll::Ready(());
+            }
+            handle.waker = Some(cx.waker().clone());
+        }
+        let new_state = match self.future.as_mut().poll(cx) {
+            Poll::Ready(Ok(value)) => ResourceState::Ready(value),
+            Poll::Ready(Err(error)) => ResourceState::Failed(error),
+            Poll::Pending => return Poll::Pending,
+        };
+        self.state.replace(new_state);
+        Poll::Ready(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instance::{Atom, Engine, Resource, ResourceState};
+    use futures_executor::LocalPool;
+    use futures_util::task::LocalSpawnExt;
+    use std::{
+        cell::RefCell,
+        future::Future,
+        pin::Pin,
+        rc::Rc,
+        task::{Context, Poll, Waker},
+    };
+
+    /// A future that the test resolves by hand.
+    #[derive(Clone, Default)]
+    struct Reply(Rc<RefCell<ReplyState>>);
+
+    #[derive(Default)]
+    struct ReplyState {
+        result: Option<Result<u32, String>>,
+        waker: Option<Waker>,
+    }
+
+    impl Reply {
+        fn resolve(&self, result: Result<u32, String>) {
+            let mut state = self.0.borrow_mut();
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }
+
+        fn is_dropped(&self) -> bool {
+            Rc::strong_count(&self.0) == 1
+        }
+    }
+
+    impl Future for Reply {
+        type Output = Result<u32, String>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let mut state = self.0.borrow_mut();
+            if let Some(result) = state.result.take() {
+                return Poll::Ready(result);
+            }
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn loads_and_refetches() {
+        let engine = Rc::new(Engine::new());
+        let mut pool = LocalPool::new();
+        let source = Atom::new(engine.clone(), 1);
+        let replies = Rc::new(RefCell::new(Vec::new()));
+        let resource = Resource::new(
+            &source,
+            {
+                let replies = replies.clone();
+                move |_: &u32| {
+                    let reply = Reply::default();
+                    replies.borrow_mut().push(reply.clone());
+                    reply
+                }
+            },
+            {
+                let spawner = pool.spawner();
+                move |future| spawner.spawn_local(future).unwrap()
+            },
+        );
+        pool.run_until_stalled();
+        assert_eq!(*resource.sample(), ResourceState::Loading);
+
+        replies.borrow()[0].resolve(Ok(10));
+        pool.run_until_stalled();
+        assert_eq!(*resource.sample(), ResourceState::Ready(10));
+
+        source.set(2);
+        assert_eq!(*resource.sample(), ResourceState::Loading);
+        replies.borrow()[1].resolve(Err("nope".to_string()));
+        pool.run_until_stalled();
+        assert_eq!(
+            *resource.sample(),
+            ResourceState::Failed("nope".to_string()),
+        );
+        drop(engine);
+    }
+
+    #[test]
+    fn stale_fetches_are_cancelled() {
+        let engine = Rc::new(Engine::new());
+        let mut pool = LocalPool::new();
+        let source = Atom::new(engine.clone(), 1);
+        let replies = Rc::new(RefCell::new(Vec::new()));
+        let resource = Resource::new(
+            &source,
+            {
+                let replies = replies.clone();
+                move |_: &u32| {
+                    let reply = Reply::default();
+                    replies.borrow_mut().push(reply.clone());
+                    reply
+                }
+            },
+            {
+                let spawner = pool.spawner();
+                move |future| spawner.spawn_local(future).unwrap()
+            },
+        );
+        pool.run_until_stalled();
+        source.set(2);
+        pool.run_until_stalled();
+        assert!(replies.borrow()[0].is_dropped());
+
+        replies.borrow()[1].resolve(Ok(20));
+        pool.run_until_stalled();
+        assert_eq!(*resource.sample(), ResourceState::Ready(20));
+
+        source.set(3);
+        drop(resource);
+        pool.run_until_stalled();
+        assert!(replies.borrow()[2].is_dropped());
+        drop(engine);
+    }
+
+    #[test]
+    fn state_is_tracked() {
+        let engine = Rc::new(Engine::new());
+        let mut pool = LocalPool::new();
+        let source = Atom::new(engine.clone(), 1);
+        let reply = Reply::default();
+        let resource = Resource::new(
+            &source,
+            {
+                let reply = reply.clone();
+                move |_: &u32| reply.clone()
+            },
+            {
+                let spawner = pool.spawner();
+                move |future| spawner.spawn_local(future).unwrap()
+            },
+        );
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let sink = sink.clone();
+            move || sink.borrow_mut().push(resource.state().clone())
+        });
+        reply.resolve(Ok(1));
+        pool.run_until_stalled();
+        source.set(2);
+        assert_eq!(*sink.borrow(), [
+            ResourceState::Loading,
+            ResourceState::Ready(1),
+            ResourceState::Loading,
+        ],);
+    }
+}
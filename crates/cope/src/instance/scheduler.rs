@@ -0,0 +1,218 @@
+/// Decides when reactions run after the atoms they depend on change.
+///
+/// Whenever an update ends with reactions still queued, the engine asks its
+/// scheduler what to do. The reactions either run right away, or stay queued
+/// until [`Engine::flush`] is called. Anything written in the meantime is
+/// queued along with them, so each reaction runs at most once per flush.
+///
+/// [`Engine::flush`]: crate::instance::Engine::flush
+pub trait Scheduler {
+    /// Returns true to run the queued reactions right away. Otherwise, the
+    /// scheduler is responsible for calling [`Engine::flush`] later.
+    ///
+    /// This is called once each time the queue goes from empty to non-empty.
+    ///
+    /// [`Engine::flush`]: crate::instance::Engine::flush
+    fn schedule(&self) -> bool;
+}
+
+/// Runs reactions as soon as whatever changed their dependencies finishes.
+/// This is the default.
+#[derive(Clone, Copy, Debug, Default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct SyncScheduler;
+
+impl Scheduler for SyncScheduler {
+    fn schedule(&self) -> bool {
+        true
+    }
+}
+
+/// Leaves reactions queued until [`Engine::flush`] is called. This is mostly
+/// useful in tests, to step through propagation one flush at a time.
+///
+/// [`Engine::flush`]: crate::instance::Engine::flush
+#[derive(Clone, Copy, Debug, Default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct ManualScheduler;
+
+impl Scheduler for ManualScheduler {
+    fn schedule(&self) -> bool {
+        false
+    }
+}
+
+/// Calls a function whenever reactions are queued, which should arrange for
+/// [`Engine::flush`] to be called later, for example from a microtask, a
+/// `tokio::task::LocalSet`, or `requestAnimationFrame`.
+///
+/// [`Engine::flush`]: crate::instance::Engine::flush
+#[allow(clippy::module_name_repetitions)]
+pub struct ExternalScheduler<F> {
+    request_flush: F,
+}
+
+impl<F: Fn()> ExternalScheduler<F> {
+    pub fn new(request_flush: F) -> Self {
+        Self { request_flush }
+    }
+}
+
+impl<F: Fn()> Scheduler for ExternalScheduler<F> {
+    fn schedule(&self) -> bool {
+        (self.request_flush)();
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instance::{Atom, Computed, Engine, ExternalScheduler, ManualScheduler};
+    use std::{
+        cell::{Cell, RefCell},
+        panic::{self, AssertUnwindSafe},
+        rc::Rc,
+    };
+
+    #[test]
+    fn manual_scheduler_waits_for_flush() {
+        let engine = Rc::new(Engine::new());
+        engine.set_scheduler(ManualScheduler);
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || sink.borrow_mut().push(*atom.get())
+        });
+        assert_eq!(*sink.borrow(), [1]);
+
+        atom.set(2);
+        atom.set(3);
+        assert_eq!(*sink.borrow(), [1]);
+        engine.flush();
+        assert_eq!(*sink.borrow(), [1, 3]);
+        engine.flush();
+        assert_eq!(*sink.borrow(), [1, 3]);
+
+        // Batches don't flush on their own either.
+        let batch = engine.batch();
+        atom.set(4);
+        drop(batch);
+        assert_eq!(*sink.borrow(), [1, 3]);
+        engine.flush();
+        assert_eq!(*sink.borrow(), [1, 3, 4]);
+    }
+
+    #[test]
+    fn manual_scheduler_steps_through_propagation() {
+        let engine = Rc::new(Engine::new());
+        engine.set_scheduler(ManualScheduler);
+        let a = Atom::new(engine.clone(), 0);
+        let b = Atom::new(engine.clone(), 0);
+        engine.react({
+            let a = a.clone();
+            let b = b.clone();
+            move || b.set(*a.get() + 1)
+        });
+        engine.flush();
+        assert_eq!(*b.sample(), 1);
+
+        // Everything queued along the way runs in the same flush.
+        a.set(10);
+        assert_eq!(*b.sample(), 1);
+        engine.flush();
+        assert_eq!(*b.sample(), 11);
+    }
+
+    #[test]
+    fn external_scheduler_requests_one_flush_at_a_time() {
+        let engine = Rc::new(Engine::new());
+        let requests = Rc::new(Cell::new(0));
+        engine.set_scheduler(ExternalScheduler::new({
+            let requests = requests.clone();
+            move || requests.set(requests.get() + 1)
+        }));
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || sink.borrow_mut().push(*atom.get())
+        });
+        assert_eq!(requests.get(), 0);
+
+        atom.set(2);
+        atom.set(3);
+        assert_eq!(requests.get(), 1);
+        assert_eq!(*sink.borrow(), [1]);
+        engine.flush();
+        assert_eq!(*sink.borrow(), [1, 3]);
+
+        atom.set(4);
+        assert_eq!(requests.get(), 2);
+    }
+
+    #[test]
+    fn computed_read_before_flush() {
+        let engine = Rc::new(Engine::new());
+        engine.set_scheduler(ManualScheduler);
+        let atom = Atom::new(engine.clone(), 1);
+        let doubled = Computed::new(engine.clone(), {
+            let atom = atom.clone();
+            move || *atom.get() * 2
+        });
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let doubled = doubled.clone();
+            let sink = sink.clone();
+            move || sink.borrow_mut().push(*doubled.get())
+        });
+
+        atom.set(5);
+        assert_eq!(*doubled.get(), 10);
+        // Reading it doesn't run the reactions early.
+        assert_eq!(*sink.borrow(), [2]);
+        engine.flush();
+        assert_eq!(*sink.borrow(), [2, 10]);
+    }
+
+    #[test]
+    fn panicking_batch_keeps_earlier_writes_pending() {
+        let engine = Rc::new(Engine::new());
+        engine.set_scheduler(ManualScheduler);
+        let a = Atom::new(engine.clone(), 0);
+        let b = Atom::new(engine.clone(), 0);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        engine.react({
+            let a = a.clone();
+            let sink = sink.clone();
+            move || sink.borrow_mut().push(*a.get())
+        });
+        let b_runs = Rc::new(Cell::new(0));
+        engine.react({
+            let b = b.clone();
+            let b_runs = b_runs.clone();
+            move || {
+                let _ = *b.get();
+                b_runs.set(b_runs.get() + 1);
+            }
+        });
+
+        a.set(1);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _batch = engine.batch();
+            b.set(1);
+            panic!("nope");
+        }));
+        assert!(result.is_err());
+        engine.flush();
+        assert_eq!(*sink.borrow(), [0, 1]);
+        // Only the batch's own work is dropped.
+        assert_eq!(b_runs.get(), 1);
+
+        b.set(2);
+        engine.flush();
+        assert_eq!(b_runs.get(), 2);
+    }
+}
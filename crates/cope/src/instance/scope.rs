@@ -0,0 +1,141 @@
+use crate::instance::{Engine, ObserverId};
+use std::{mem, rc::Rc};
+
+/// The root of an ownership tree, created by [`Engine::create_root`].
+///
+/// Every reaction and computed value created inside the root is owned by it,
+/// either directly or through the reaction that created it. Dropping the scope
+/// disposes all of them. Call [`leak`] to keep them alive forever instead.
+///
+/// [`Engine::create_root`]: crate::instance::Engine::create_root
+/// [`leak`]: Scope::leak
+#[must_use]
+pub struct Scope {
+    engine: Rc<Engine>,
+    root: ObserverId,
+}
+
+impl Scope {
+    pub(crate) fn new(engine: Rc<Engine>, root: ObserverId) -> Self {
+        Self { engine, root }
+    }
+
+    pub fn dispose(self) {
+        drop(self);
+    }
+
+    pub fn leak(self) {
+        mem::forget(self);
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        self.engine.dispose(self.root);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instance::{Atom, Computed, Engine};
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn dropping_root_disposes_reactions() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let (scope, ()) = engine.create_root(|| {
+            engine.react({
+                let atom = atom.clone();
+                let sink = sink.clone();
+                move || {
+                    sink.borrow_mut().push(*atom.get());
+                }
+            });
+        });
+        atom.set(2);
+        drop(scope);
+        atom.set(3);
+        assert_eq!(*sink.borrow(), [1, 2]);
+    }
+
+    #[test]
+    fn root_does_not_track() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let (scope, value) = engine.create_root(|| *atom.get());
+        assert_eq!(value, 1);
+        engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || {
+                sink.borrow_mut().push(*atom.get());
+            }
+        });
+        atom.set(2);
+        assert_eq!(*sink.borrow(), [1, 2]);
+        scope.leak();
+    }
+
+    #[test]
+    fn rerun_disposes_children() {
+        let engine = Rc::new(Engine::new());
+        let outer = Atom::new(engine.clone(), 0);
+        let inner = Atom::new(engine.clone(), 0);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let (_scope, ()) = engine.create_root(|| {
+            engine.react({
+                let engine = engine.clone();
+                let outer = outer.clone();
+                let inner = inner.clone();
+                let sink = sink.clone();
+                move || {
+                    let generation = *outer.get();
+                    engine.react({
+                        let inner = inner.clone();
+                        let sink = sink.clone();
+                        move || {
+                            sink.borrow_mut().push((generation, *inner.get()));
+                        }
+                    });
+                }
+            });
+        });
+        outer.set(1);
+        sink.borrow_mut().clear();
+
+        // Only the child from the latest run is still alive.
+        inner.set(5);
+        assert_eq!(*sink.borrow(), [(1, 5)]);
+    }
+
+    #[test]
+    fn dispose_disposes_children() {
+        let engine = Rc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let reaction = engine.react({
+            let engine = engine.clone();
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || {
+                let doubled = Computed::new(engine.clone(), {
+                    let atom = atom.clone();
+                    move || *atom.get() * 2
+                });
+                engine.react({
+                    let sink = sink.clone();
+                    move || {
+                        sink.borrow_mut().push(*doubled.get());
+                    }
+                });
+            }
+        });
+        atom.set(2);
+        reaction.dispose();
+        atom.set(3);
+        assert_eq!(*sink.borrow(), [2, 4]);
+    }
+}
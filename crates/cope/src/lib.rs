@@ -3,5 +3,11 @@
 // #![warn(clippy::cargo)]
 #![cfg_attr(feature = "strict", deny(warnings))]
 
+pub use self::error::Error;
+
+pub mod collections;
 pub mod instance;
 pub mod singleton;
+pub mod sync;
+
+mod error;
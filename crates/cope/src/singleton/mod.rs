@@ -1,19 +1,103 @@
-use crate::{instance, instance::AtomMut};
+pub use self::selector::Selector;
+#[cfg(feature = "futures")]
+pub use crate::instance::{Changed, Values};
+pub use crate::instance::{PanicPolicy, Reaction, ReactionGuard, ResourceState, Scope};
+
+use crate::{instance, instance::AtomMut, Error};
 use std::{
-    cell::{Ref, RefMut},
-    sync::Arc,
+    any::Any,
+    borrow::Cow,
+    cell::{Ref, RefCell, RefMut},
+    future::Future,
+    pin::Pin,
+    rc::Rc,
 };
 
+mod selector;
+
 thread_local! {
-    static ENGINE: Arc<instance::Engine> = Arc::new(instance::Engine::new());
+    static ENGINE: RefCell<Rc<instance::Engine>> = RefCell::new(Rc::new(instance::Engine::new()));
+}
+
+/// Returns the engine that atoms and reactions on this thread are created on.
+#[must_use]
+pub fn engine() -> Rc<instance::Engine> {
+    ENGINE.with(|engine| engine.borrow().clone())
+}
+
+/// Replaces the current thread's engine, and returns the old one. Atoms and
+/// reactions that were already created stay on the engine they were created on.
+pub fn set_engine(engine: Rc<instance::Engine>) -> Rc<instance::Engine> {
+    ENGINE.with(|current| current.replace(engine))
+}
+
+/// Replaces the current thread's engine with a new, empty one.
+pub fn reset_engine() {
+    set_engine(Rc::new(instance::Engine::new()));
+}
+
+/// Runs `f` with `engine` as the current thread's engine, then puts the old
+/// one back, even if `f` panics.
+pub fn with_engine<T>(engine: Rc<instance::Engine>, f: impl FnOnce() -> T) -> T {
+    let previous = set_engine(engine);
+    let _restore = scopeguard::guard(previous, |previous| {
+        set_engine(previous);
+    });
+    f()
 }
 
 pub fn batch() -> Batch {
-    ENGINE.with(|engine| Batch::new(engine.batch()))
+    Batch::new(engine().batch())
+}
+
+pub fn react(f: impl FnMut() + 'static) -> Reaction {
+    engine().react(f)
+}
+
+pub fn untrack<T>(f: impl FnOnce() -> T) -> T {
+    engine().untrack(f)
+}
+
+pub fn on_cleanup(f: impl FnOnce() + 'static) {
+    engine().on_cleanup(f)
+}
+
+#[must_use]
+pub fn is_tracking() -> bool {
+    engine().is_tracking()
+}
+
+pub fn set_panic_policy(policy: PanicPolicy) {
+    engine().set_panic_policy(policy)
+}
+
+pub fn set_error_handler(handler: impl Fn(Box<dyn Any + Send>) + 'static) {
+    engine().set_error_handler(handler)
+}
+
+pub fn create_root<T>(f: impl FnOnce() -> T) -> (Scope, T) {
+    engine().create_root(f)
+}
+
+pub fn set_scheduler(scheduler: impl instance::Scheduler + 'static) {
+    engine().set_scheduler(scheduler)
+}
+
+pub fn flush() {
+    engine().flush()
+}
+
+/// # Errors
+///
+/// See [`instance::Engine::try_flush`].
+pub fn try_flush() -> Result<(), Error> {
+    engine().try_flush()
 }
 
-pub fn react(f: impl FnMut() + 'static) {
-    ENGINE.with(|engine| engine.react(f))
+/// See [`instance::Engine::stream`].
+#[cfg(feature = "futures")]
+pub fn stream<T: Clone + 'static>(f: impl FnMut() -> T + 'static) -> Values<T> {
+    engine().stream(f)
 }
 
 #[must_use]
@@ -34,30 +118,139 @@ pub struct Atom<T> {
 
 impl<T: 'static> Atom<T> {
     pub fn new(initial_value: T) -> Self {
-        let engine = ENGINE.with(<_>::clone);
+        let engine = engine();
         Self {
             inner: instance::Atom::new(engine, initial_value),
         }
     }
 
+    pub fn with_equality(initial_value: T, equality: fn(&T, &T) -> bool) -> Self {
+        let engine = engine();
+        Self {
+            inner: instance::Atom::with_equality(engine, initial_value, equality),
+        }
+    }
+
+    /// Gives the atom a name, which is used to identify it in error messages.
+    #[must_use]
+    pub fn named(self, name: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            inner: self.inner.named(name),
+        }
+    }
+
     #[must_use]
     pub fn get(&self) -> Ref<'_, T> {
         self.inner.get()
     }
 
+    /// # Errors
+    ///
+    /// See [`instance::Atom::try_get`].
+    pub fn try_get(&self) -> Result<Ref<'_, T>, Error> {
+        self.inner.try_get()
+    }
+
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        self.inner.with(f)
+    }
+
+    #[must_use]
+    pub fn get_cloned(&self) -> T
+    where
+        T: Clone,
+    {
+        self.inner.get_cloned()
+    }
+
+    #[must_use]
+    pub fn sample(&self) -> Ref<'_, T> {
+        self.inner.sample()
+    }
+
     #[must_use]
     pub fn get_mut(&self) -> AtomMut<'_, T> {
         self.inner.get_mut()
     }
 
+    /// # Errors
+    ///
+    /// See [`instance::Atom::try_get_mut`].
+    pub fn try_get_mut(&self) -> Result<AtomMut<'_, T>, Error> {
+        self.inner.try_get_mut()
+    }
+
     #[must_use]
     pub fn sample_mut(&self) -> RefMut<'_, T> {
         self.inner.sample_mut()
     }
 
-    pub fn set(&self, value: T) {
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        self.inner.update(f)
+    }
+
+    pub fn replace(&self, value: T) -> T {
+        self.inner.replace(value)
+    }
+
+    #[must_use]
+    pub fn take(&self) -> T
+    where
+        T: Default,
+    {
+        self.inner.take()
+    }
+
+    pub fn set(&self, value: T)
+    where
+        T: PartialEq,
+    {
         self.inner.set(value);
     }
+
+    /// # Errors
+    ///
+    /// See [`instance::Atom::try_set`].
+    pub fn try_set(&self, value: T) -> Result<(), Error>
+    where
+        T: PartialEq,
+    {
+        self.inner.try_set(value)
+    }
+
+    pub fn overwrite(&self, value: T) {
+        self.inner.overwrite(value);
+    }
+
+    /// # Errors
+    ///
+    /// See [`instance::Atom::try_set`].
+    pub fn try_overwrite(&self, value: T) -> Result<(), Error> {
+        self.inner.try_overwrite(value)
+    }
+
+    /// See [`instance::Atom::downgrade`].
+    #[must_use]
+    pub fn downgrade(&self) -> WeakAtom<T> {
+        WeakAtom {
+            inner: self.inner.downgrade(),
+        }
+    }
+
+    /// See [`instance::Atom::changed`].
+    #[cfg(feature = "futures")]
+    pub fn changed(&self) -> Changed {
+        self.inner.changed()
+    }
+
+    /// See [`instance::Atom::stream`].
+    #[cfg(feature = "futures")]
+    pub fn stream(&self) -> Values<T>
+    where
+        T: Clone,
+    {
+        self.inner.stream()
+    }
 }
 
 impl<T: Default + 'static> Default for Atom<T> {
@@ -73,3 +266,164 @@ impl<T> Clone for Atom<T> {
         }
     }
 }
+
+pub struct WeakAtom<T> {
+    inner: instance::WeakAtom<T>,
+}
+
+impl<T> WeakAtom<T> {
+    #[must_use]
+    pub fn upgrade(&self) -> Option<Atom<T>> {
+        Some(Atom {
+            inner: self.inner.upgrade()?,
+        })
+    }
+}
+
+impl<T> Clone for WeakAtom<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+pub struct Computed<T> {
+    inner: instance::Computed<T>,
+}
+
+impl<T: 'static> Computed<T> {
+    pub fn new(f: impl FnMut() -> T + 'static) -> Self {
+        let engine = engine();
+        Self {
+            inner: instance::Computed::new(engine, f),
+        }
+    }
+
+    #[must_use]
+    pub fn named(self, name: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            inner: self.inner.named(name),
+        }
+    }
+
+    #[must_use]
+    pub fn get(&self) -> Ref<'_, T> {
+        self.inner.get()
+    }
+
+    /// # Errors
+    ///
+    /// See [`instance::Computed::try_get`].
+    pub fn try_get(&self) -> Result<Ref<'_, T>, Error> {
+        self.inner.try_get()
+    }
+
+    #[must_use]
+    pub fn sample(&self) -> Ref<'_, T> {
+        self.inner.sample()
+    }
+
+    /// # Errors
+    ///
+    /// See [`instance::Computed::try_sample`].
+    pub fn try_sample(&self) -> Result<Ref<'_, T>, Error> {
+        self.inner.try_sample()
+    }
+
+    /// See [`instance::Computed::changed`].
+    #[cfg(feature = "futures")]
+    pub fn changed(&self) -> Changed {
+        self.inner.changed()
+    }
+
+    /// See [`instance::Computed::stream`].
+    #[cfg(feature = "futures")]
+    pub fn stream(&self) -> Values<T>
+    where
+        T: Clone,
+    {
+        self.inner.stream()
+    }
+}
+
+impl<T> Clone for Computed<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+pub struct Resource<T, E> {
+    inner: instance::Resource<T, E>,
+}
+
+impl<T: 'static, E: 'static> Resource<T, E> {
+    /// See [`instance::Resource::new`].
+    pub fn new<S: 'static, Fut>(
+        source: &Atom<S>,
+        fetch: impl FnMut(&S) -> Fut + 'static,
+        spawn: impl Fn(Pin<Box<dyn Future<Output = ()>>>) + 'static,
+    ) -> Self
+    where
+        Fut: Future<Output = Result<T, E>> + 'static,
+    {
+        Self {
+            inner: instance::Resource::new(&source.inner, fetch, spawn),
+        }
+    }
+
+    #[must_use]
+    pub fn state(&self) -> Ref<'_, ResourceState<T, E>> {
+        self.inner.state()
+    }
+
+    #[must_use]
+    pub fn sample(&self) -> Ref<'_, ResourceState<T, E>> {
+        self.inner.sample()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        instance,
+        singleton::{engine, react, with_engine, Atom},
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn with_engine_binds_atoms_and_reactions() {
+        let isolated = Rc::new(instance::Engine::new());
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        let atom = with_engine(isolated.clone(), || {
+            assert!(Rc::ptr_eq(&engine(), &isolated));
+            let atom = Atom::new(1);
+            react({
+                let atom = atom.clone();
+                let sink = sink.clone();
+                move || sink.borrow_mut().push(*atom.get())
+            });
+            atom
+        });
+        assert!(!Rc::ptr_eq(&engine(), &isolated));
+
+        // The atom and reaction stay on the engine they were created on.
+        let batch = isolated.batch();
+        atom.set(2);
+        assert_eq!(*sink.borrow(), [1]);
+        drop(batch);
+        assert_eq!(*sink.borrow(), [1, 2]);
+    }
+
+    #[test]
+    fn with_engine_restores_after_panic() {
+        let before = engine();
+        let result = std::panic::catch_unwind(|| {
+            with_engine(Rc::new(instance::Engine::new()), || panic!("oops"));
+        });
+        assert!(result.is_err());
+        assert!(Rc::ptr_eq(&engine(), &before));
+    }
+}
@@ -0,0 +1,201 @@
+use crate::singleton::{batch, is_tracking, on_cleanup, Atom};
+use std::{
+    cell::{Ref, RefCell},
+    collections::HashMap,
+    hash::Hash,
+    mem,
+    rc::Rc,
+};
+
+/// Tracks which single key out of many is selected, such that each reader only
+/// depends on its own key.
+///
+/// Checking [`is`] from a reaction subscribes it to that key alone. Changing
+/// the selection notifies the reactions for the old key and the new key, and
+/// no others.
+///
+/// [`is`]: Selector::is
+pub struct Selector<K> {
+    selected: Atom<K>,
+    keys: Rc<RefCell<HashMap<K, Key>>>,
+}
+
+struct Key {
+    is_selected: Atom<bool>,
+    /// The number of reactions currently reading this key.
+    readers: usize,
+}
+
+impl<K: Clone + Eq + Hash + 'static> Selector<K> {
+    pub fn new(initial: K) -> Self {
+        Self {
+            selected: Atom::new(initial),
+            keys: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the selected key, subscribing the current reaction to every
+    /// change.
+    #[must_use]
+    pub fn get(&self) -> Ref<'_, K> {
+        self.selected.get()
+    }
+
+    /// Returns whether `key` is selected, subscribing the current reaction only
+    /// to changes that affect `key`.
+    #[must_use]
+    pub fn is(&self, key: &K) -> bool {
+        // Nothing would ever forget the key, say inside a root, so don't keep
+        // one.
+        if !is_tracking() {
+            return *self.selected.sample() == *key;
+        }
+
+        let is_selected = {
+            let mut keys = self.keys.borrow_mut();
+            let entry = keys.entry(key.clone()).or_insert_with(|| {
+                Key {
+                    is_selected: Atom::new(*self.selected.sample() == *key),
+                    readers: 0,
+                }
+            });
+            entry.readers += 1;
+            entry.is_selected.clone()
+        };
+        let result = *is_selected.get();
+
+        // Forget the key once nobody is reading it.
+        let reader = Reader {
+            keys: self.keys.clone(),
+            key: key.clone(),
+        };
+        on_cleanup(move || drop(reader));
+
+        result
+    }
+
+    pub fn select(&self, key: K) {
+        if *self.selected.sample() == key {
+            return;
+        }
+
+        let _batch = batch();
+        let previous = mem::replace(&mut *self.selected.get_mut(), key);
+        let (previous, next) = {
+            let keys = self.keys.borrow();
+            let is_selected = |key| keys.get(key).map(|k: &Key| k.is_selected.clone());
+            (is_selected(&previous), is_selected(&self.selected.sample()))
+        };
+        if let Some(previous) = previous {
+            previous.set(false);
+        }
+        if let Some(next) = next {
+            next.set(true);
+        }
+    }
+}
+
+impl<K: Clone + Default + Eq + Hash + 'static> Default for Selector<K> {
+    fn default() -> Self {
+        Self::new(K::default())
+    }
+}
+
+impl<K> Clone for Selector<K> {
+    fn clone(&self) -> Self {
+        Self {
+            selected: self.selected.clone(),
+            keys: self.keys.clone(),
+        }
+    }
+}
+
+struct Reader<K: Eq + Hash> {
+    keys: Rc<RefCell<HashMap<K, Key>>>,
+    key: K,
+}
+
+impl<K: Eq + Hash> Drop for Reader<K> {
+    fn drop(&mut self) {
+        let mut keys = self.keys.borrow_mut();
+        let entry = keys.get_mut(&self.key).unwrap();
+        entry.readers -= 1;
+        if entry.readers == 0 {
+            keys.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::singleton::{create_root, react, untrack, Selector};
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn only_old_and_new_keys_are_notified() {
+        let selector = Selector::new(0);
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        for key in 0..5 {
+            react({
+                let selector = selector.clone();
+                let sink = sink.clone();
+                move || {
+                    sink.borrow_mut().push((key, selector.is(&key)));
+                }
+            });
+        }
+        sink.borrow_mut().clear();
+
+        selector.select(3);
+        assert_eq!(*sink.borrow(), [(0, false), (3, true)]);
+        sink.borrow_mut().clear();
+
+        selector.select(3);
+        assert_eq!(*sink.borrow(), []);
+
+        selector.select(4);
+        assert_eq!(*sink.borrow(), [(3, false), (4, true)]);
+    }
+
+    #[test]
+    fn forgets_keys_nobody_reads() {
+        let selector = Selector::new(0);
+        assert!(selector.is(&0));
+        assert!(!selector.is(&1));
+        assert!(selector.keys.borrow().is_empty());
+
+        let reaction = react({
+            let selector = selector.clone();
+            move || {
+                let _ = selector.is(&1);
+            }
+        });
+        assert_eq!(selector.keys.borrow().len(), 1);
+        selector.select(1);
+        assert_eq!(selector.keys.borrow().len(), 1);
+        reaction.dispose();
+        assert!(selector.keys.borrow().is_empty());
+    }
+
+    #[test]
+    fn untracked_reads_keep_nothing() {
+        let selector = Selector::new(0);
+        let (scope, ()) = create_root(|| {
+            for _ in 0..3 {
+                assert!(selector.is(&0));
+                assert!(!selector.is(&1));
+            }
+        });
+        assert!(selector.keys.borrow().is_empty());
+
+        let reaction = react({
+            let selector = selector.clone();
+            move || {
+                let _ = untrack(|| selector.is(&1));
+            }
+        });
+        assert!(selector.keys.borrow().is_empty());
+        reaction.dispose();
+        scope.dispose();
+    }
+}
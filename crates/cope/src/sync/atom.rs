@@ -0,0 +1,478 @@
+use crate::{
+    sync::engine::{Engine, Source},
+    Error,
+};
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::{
+    any,
+    borrow::Cow,
+    cell::RefCell,
+    mem,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Weak},
+    thread,
+};
+
+/// A piece of state that reactions can depend on, shareable between threads.
+///
+/// Like its counterpart in [`cope::instance`](crate::instance), it doesn't
+/// keep its engine alive. Once the engine is dropped, reading and writing
+/// still work, but nothing is notified.
+pub struct Atom<T> {
+    inner: Arc<Inner<T>>,
+}
+
+struct Inner<T> {
+    engine: Weak<Engine>,
+    source: Arc<Source>,
+    /// Guards on other threads only ever make this wait for each other, never
+    /// for the engine.
+    value: RwLock<T>,
+    /// Decides whether `set` changed anything. Without one, it always notifies.
+    equality: Option<fn(&T, &T) -> bool>,
+}
+
+thread_local! {
+    /// The atoms this thread holds a guard for, by address. A conflicting
+    /// borrow of one of those on the same thread would wait forever, so it
+    /// fails instead.
+    static HELD: RefCell<Vec<usize>> = RefCell::new(Vec::new());
+}
+
+/// Marks an atom as held by this thread until it's dropped.
+struct Held(usize);
+
+impl Held {
+    fn new(address: usize) -> Self {
+        HELD.with(|held| held.borrow_mut().push(address));
+        Self(address)
+    }
+
+    fn is_held(address: usize) -> bool {
+        HELD.with(|held| held.borrow().contains(&address))
+    }
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        HELD.with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(index) = held.iter().position(|&a| a == self.0) {
+                held.swap_remove(index);
+            }
+        });
+    }
+}
+
+impl<T> Atom<T> {
+    pub fn new(engine: Arc<Engine>, initial_value: T) -> Self {
+        Self::with_equality_option(engine, initial_value, None)
+    }
+
+    /// Creates an atom whose [`set`] and [`overwrite`] use `equality` to decide
+    /// whether the value changed, and skip notifying subscribers if it didn't.
+    ///
+    /// [`set`]: Atom::set
+    /// [`overwrite`]: Atom::overwrite
+    pub fn with_equality(
+        engine: Arc<Engine>,
+        initial_value: T,
+        equality: fn(&T, &T) -> bool,
+    ) -> Self {
+        Self::with_equality_option(engine, initial_value, Some(equality))
+    }
+
+    // Takes an `Arc` like the other constructors, even though it only keeps a
+    // weak reference.
+    #[allow(clippy::needless_pass_by_value)]
+    fn with_equality_option(
+        engine: Arc<Engine>,
+        initial_value: T,
+        equality: Option<fn(&T, &T) -> bool>,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                engine: Arc::downgrade(&engine),
+                source: Arc::new(Source::new(any::type_name::<Self>())),
+                value: RwLock::new(initial_value),
+                equality,
+            }),
+        }
+    }
+
+    /// Gives the atom a name, which is used to identify it in error messages.
+    #[must_use]
+    pub fn named(self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.inner.source.set_name(name.into());
+        self
+    }
+
+    /// Reads the value, and subscribes the current reaction to it.
+    ///
+    /// Writers on other threads wait until the guard is dropped, so keep it
+    /// short. Prefer [`with`] or [`get_cloned`].
+    ///
+    /// [`with`]: Atom::with
+    /// [`get_cloned`]: Atom::get_cloned
+    #[must_use]
+    pub fn get(&self) -> AtomRef<'_, T> {
+        self.try_get().unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like [`get`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AlreadyBorrowed`] if this thread is currently writing
+    /// the value, or [`Error::WrongEngine`] if a reaction from a different
+    /// engine is running.
+    ///
+    /// [`get`]: Atom::get
+    pub fn try_get(&self) -> Result<AtomRef<'_, T>, Error> {
+        // Subscribe before reading, so that a write from another thread in
+        // between still reaches the reaction.
+        if let Some(engine) = self.inner.engine.upgrade() {
+            engine.track(&self.inner.source)?;
+        }
+        self.try_sample()
+    }
+
+    /// Calls `f` with a reference to the value, and subscribes the current
+    /// reaction to it.
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&self.get())
+    }
+
+    /// Returns a clone of the value, and subscribes the current reaction to it.
+    #[must_use]
+    pub fn get_cloned(&self) -> T
+    where
+        T: Clone,
+    {
+        self.get().clone()
+    }
+
+    /// Reads the value without subscribing the current reaction to it.
+    #[must_use]
+    pub fn sample(&self) -> AtomRef<'_, T> {
+        self.try_sample()
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    fn try_sample(&self) -> Result<AtomRef<'_, T>, Error> {
+        // Recursive, so that a writer waiting on another thread doesn't make
+        // this thread's second read wait for its first one.
+        let value = match self.inner.value.try_read_recursive() {
+            Some(value) => value,
+            None if Held::is_held(self.address()) => return Err(self.already_borrowed()),
+            // Whoever has it is on another thread, and will let go eventually.
+            None => self.inner.value.read_recursive(),
+        };
+        Ok(AtomRef {
+            value,
+            _held: Held::new(self.address()),
+        })
+    }
+
+    #[must_use]
+    pub fn get_mut(&self) -> AtomMut<'_, T> {
+        self.try_get_mut()
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like [`get_mut`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AlreadyBorrowed`] if this thread is currently reading
+    /// or writing the value.
+    ///
+    /// [`get_mut`]: Atom::get_mut
+    pub fn try_get_mut(&self) -> Result<AtomMut<'_, T>, Error> {
+        let value = match self.inner.value.try_write() {
+            Some(value) => value,
+            None if Held::is_held(self.address()) => return Err(self.already_borrowed()),
+            None => self.inner.value.write(),
+        };
+        Ok(AtomMut {
+            engine: &self.inner.engine,
+            source: &self.inner.source,
+            value: Some(value),
+            held: Some(Held::new(self.address())),
+            changed: true,
+        })
+    }
+    /// Calls `f` with a mutable reference to the value, then notifies
+    /// subscribers once it returns.
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.get_mut())
+    }
+
+    /// Replaces the value and returns the old one. Unlike [`set`], this always
+    /// notifies subscribers.
+    ///
+    /// [`set`]: Atom::set
+    pub fn replace(&self, value: T) -> T {
+        self.update(|old| mem::replace(old, value))
+    }
+
+    /// Takes the value, leaving the default in its place.
+    #[must_use]
+    pub fn take(&self) -> T
+    where
+        T: Default,
+    {
+        self.replace(T::default())
+    }
+
+    /// Replaces the value and notifies subscribers, unless the new value is
+    /// equal to the old one. Equality is decided by the comparator from
+    /// [`with_equality`] if there is one, and by `PartialEq` otherwise.
+    ///
+    /// [`with_equality`]: Atom::with_equality
+    pub fn set(&self, value: T)
+    where
+        T: PartialEq,
+    {
+        if let Err(error) = self.try_set(value) {
+            panic!("{}", error);
+        }
+    }
+
+    /// Like [`set`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AlreadyBorrowed`] if this thread is currently reading
+    /// or writing the value, or [`Error::CycleDetected`] if the reactions this
+    /// write triggers don't settle down.
+    ///
+    /// [`set`]: Atom::set
+    pub fn try_set(&self, value: T) -> Result<(), Error>
+    where
+        T: PartialEq,
+    {
+        let equality = self.inner.equality.unwrap_or(T::eq);
+        self.try_set_unless(value, Some(equality))
+    }
+
+    /// Like [`set`], but works for any `T`. Only the comparator from
+    /// [`with_equality`] is consulted, so without one, subscribers are always
+    /// notified.
+    ///
+    /// [`set`]: Atom::set
+    /// [`with_equality`]: Atom::with_equality
+    pub fn overwrite(&self, value: T) {
+        if let Err(error) = self.try_overwrite(value) {
+            panic!("{}", error);
+        }
+    }
+
+    /// Like [`overwrite`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// The same as [`try_set`].
+    ///
+    /// [`overwrite`]: Atom::overwrite
+    /// [`try_set`]: Atom::try_set
+    pub fn try_overwrite(&self, value: T) -> Result<(), Error> {
+        self.try_set_unless(value, self.inner.equality)
+    }
+
+    /// Writes `value`, unless `equality` says it's the same as the old one.
+    fn try_set_unless(&self, value: T, equality: Option<fn(&T, &T) -> bool>) -> Result<(), Error> {
+        let mut guard = self.try_get_mut()?;
+        // Notify manually below, so that errors can be returned.
+        AtomMut::mark_unchanged(&mut guard);
+        if let Some(equality) = equality {
+            if equality(&guard, &value) {
+                return Ok(());
+            }
+        }
+        *guard = value;
+        drop(guard);
+        match self.inner.engine.upgrade() {
+            Some(engine) => engine.try_write(&self.inner.source),
+            None => Ok(()),
+        }
+    }
+
+    fn already_borrowed(&self) -> Error {
+        Error::AlreadyBorrowed {
+            name: self.inner.source.describe(),
+        }
+    }
+
+    fn address(&self) -> usize {
+        &*self.inner as *const Inner<T> as usize
+    }
+}
+
+impl<T> Clone for Atom<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        if let Some(engine) = self.engine.upgrade() {
+            engine.remove_source(&self.source);
+        }
+    }
+}
+
+/// A read guard for an atom's value. Writers wait until it's dropped, but
+/// the rest of the engine doesn't.
+#[allow(clippy::module_name_repetitions)]
+pub struct AtomRef<'a, T> {
+    value: RwLockReadGuard<'a, T>,
+    _held: Held,
+}
+
+impl<T> Deref for AtomRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.value
+    }
+}
+
+/// A write guard for an atom's value. Subscribers are notified when it's
+/// dropped.
+#[allow(clippy::module_name_repetitions)]
+pub struct AtomMut<'a, T> {
+    engine: &'a Weak<Engine>,
+    source: &'a Arc<Source>,
+    // Option dance
+    value: Option<RwLockWriteGuard<'a, T>>,
+    held: Option<Held>,
+    changed: bool,
+}
+
+impl<T> AtomMut<'_, T> {
+    /// Declares that the value didn't actually change, so nothing is notified
+    /// when the guard is dropped.
+    ///
+    /// This is an associated function so it doesn't shadow methods on `T`.
+    pub fn mark_unchanged(this: &mut Self) {
+        this.changed = false;
+    }
+}
+
+impl<T> Deref for AtomMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.value.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for AtomMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut *self.value.as_mut().unwrap()
+    }
+}
+
+impl<T> Drop for AtomMut<'_, T> {
+    fn drop(&mut self) {
+        drop(self.value.take());
+        drop(self.held.take());
+        // If whoever held the guard panicked, the value may be half-written,
+        // so don't tell anyone about it, and don't run reactions mid-unwind.
+        if !self.changed || thread::panicking() {
+            return;
+        }
+
+        // If we're already inside a batch or a reaction, or another thread is
+        // running reactions, this only queues them, and they run once that
+        // finishes.
+        if let Some(engine) = self.engine.upgrade() {
+            engine.write(self.source);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        sync::{Atom, Engine},
+        Error,
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn set_equal_value_does_not_notify() {
+        let engine = Arc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let runs = Arc::new(Mutex::new(0));
+        engine.react({
+            let atom = atom.clone();
+            let runs = runs.clone();
+            move || {
+                drop(atom.get());
+                *runs.lock().unwrap() += 1;
+            }
+        });
+        atom.set(1);
+        assert_eq!(*runs.lock().unwrap(), 1);
+        assert_eq!(atom.replace(1), 1);
+        assert_eq!(*runs.lock().unwrap(), 2);
+        atom.overwrite(1);
+        assert_eq!(*runs.lock().unwrap(), 3);
+    }
+
+    #[test]
+    fn with_equality() {
+        let engine = Arc::new(Engine::new());
+        let atom = Atom::with_equality(engine.clone(), 10, |a, b| a / 10 == b / 10);
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || sink.lock().unwrap().push(*atom.get())
+        });
+        atom.set(15);
+        atom.set(25);
+        assert_eq!(*sink.lock().unwrap(), [10, 25]);
+    }
+
+    #[test]
+    fn write_inside_reaction() {
+        let engine = Arc::new(Engine::new());
+        let a = Atom::new(engine.clone(), 0);
+        let b = Atom::new(engine.clone(), 0);
+        engine.react({
+            let a = a.clone();
+            let b = b.clone();
+            move || b.set(a.get_cloned() + 1)
+        });
+        a.set(5);
+        assert_eq!(*b.sample(), 6);
+    }
+
+    #[test]
+    fn write_while_borrowed() {
+        let engine = Arc::new(Engine::new());
+        let atom = Atom::new(engine, 0).named("count");
+        let guard = atom.get();
+        // Reading again on the same thread is fine.
+        assert_eq!(*atom.get(), 0);
+        assert_eq!(
+            atom.try_set(1),
+            Err(Error::AlreadyBorrowed {
+                name: "`count`".into(),
+            }),
+        );
+        drop(guard);
+        assert_eq!(atom.try_set(1), Ok(()));
+
+        let guard = atom.get_mut();
+        assert!(matches!(atom.try_get(), Err(Error::AlreadyBorrowed { .. })));
+        drop(guard);
+        assert_eq!(*atom.get(), 1);
+    }
+}
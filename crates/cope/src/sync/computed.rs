@@ -0,0 +1,247 @@
+use crate::{
+    sync::engine::{Engine, Observer, Source},
+    Error,
+};
+use parking_lot::{Mutex, ReentrantMutex};
+use std::{
+    any,
+    borrow::Cow,
+    sync::{Arc, Weak},
+};
+
+/// A value derived from other atoms, shareable between threads.
+///
+/// The closure runs lazily the first time the value is read, and the result is
+/// cached until one of the atoms it read changes. Reading a computed value
+/// inside a reaction subscribes the reaction to it, just like an atom.
+///
+/// Reads return a shared snapshot of the value rather than a guard, so holding
+/// on to one never blocks a recompute on another thread.
+///
+/// Like an atom, it doesn't keep its engine alive. Once the engine is dropped,
+/// reading it returns [`Error::Disposed`].
+pub struct Computed<T> {
+    inner: Arc<Inner<T>>,
+}
+
+struct Inner<T> {
+    engine: Weak<Engine>,
+    value: Arc<Mutex<Option<Arc<T>>>>,
+    observer: Arc<Observer>,
+    source: Arc<Source>,
+    /// Held while recomputing, so that two threads don't both do it.
+    computing: ReentrantMutex<()>,
+}
+
+impl<T: Send + Sync + 'static> Computed<T> {
+    // Takes an `Arc` like the other constructors, even though it only keeps a
+    // weak reference.
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn new(engine: Arc<Engine>, mut f: impl FnMut() -> T + Send + 'static) -> Self {
+        let value = Arc::new(Mutex::new(None));
+        let source = Arc::new(Source::new(any::type_name::<Self>()));
+        let observer = engine.add_computed(&source, {
+            let value = value.clone();
+            move || {
+                let new_value = Arc::new(f());
+                *value.lock() = Some(new_value);
+            }
+        });
+        Self {
+            inner: Arc::new(Inner {
+                engine: Arc::downgrade(&engine),
+                value,
+                observer,
+                source,
+                computing: ReentrantMutex::new(()),
+            }),
+        }
+    }
+
+    /// Gives the computed value a name, which is used to identify it in error
+    /// messages.
+    #[must_use]
+    pub fn named(self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.inner.source.set_name(name.into());
+        self
+    }
+
+    #[must_use]
+    pub fn get(&self) -> Arc<T> {
+        self.try_get().unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like [`get`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Disposed`] if the reaction that owned this was
+    /// disposed or the engine was dropped, [`Error::AlreadyBorrowed`] if it's
+    /// read while it's being computed on the same thread, or
+    /// [`Error::WrongEngine`] if a reaction from a different engine is
+    /// running.
+    ///
+    /// [`get`]: Computed::get
+    pub fn try_get(&self) -> Result<Arc<T>, Error> {
+        // Subscribe before reading, like atoms do.
+        if let Some(engine) = self.inner.engine.upgrade() {
+            engine.track(&self.inner.source)?;
+        }
+        self.try_sample()
+    }
+
+    /// Reads the value, recomputing it if needed, without subscribing the
+    /// current reaction to it.
+    #[must_use]
+    pub fn sample(&self) -> Arc<T> {
+        self.try_sample()
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like [`sample`], but returns an error instead of panicking.
+    ///
+    /// # Errors
+    ///
+    /// The same as [`try_get`].
+    ///
+    /// [`sample`]: Computed::sample
+    /// [`try_get`]: Computed::try_get
+    pub fn try_sample(&self) -> Result<Arc<T>, Error> {
+        let Inner {
+            engine,
+            value,
+            observer,
+            source,
+            computing,
+        } = &*self.inner;
+        let disposed = || {
+            Error::Disposed {
+                name: source.describe(),
+            }
+        };
+        let engine = engine.upgrade().ok_or_else(disposed)?;
+        if observer.is_disposed() {
+            return Err(disposed());
+        }
+        if observer.is_stale() {
+            let _computing = computing.lock();
+            // Another thread may have recomputed it while this one waited.
+            if observer.take_stale() {
+                engine.run(observer);
+            }
+        }
+        // Only empty if the closure is reading its own value.
+        value.lock().clone().ok_or_else(|| {
+            Error::AlreadyBorrowed {
+                name: source.describe(),
+            }
+        })
+    }
+}
+
+impl<T> Clone for Computed<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        // Nobody can read the value anymore, so stop tracking its sources.
+        self.observer.dispose();
+        if let Some(engine) = self.engine.upgrade() {
+            engine.remove_source(&self.source);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sync::{Atom, Computed, Engine};
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    #[test]
+    fn lazy_and_memoized() {
+        let engine = Arc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let calls = Arc::new(Mutex::new(0));
+        let doubled = Computed::new(engine.clone(), {
+            let atom = atom.clone();
+            let calls = calls.clone();
+            move || {
+                *calls.lock().unwrap() += 1;
+                *atom.get() * 2
+            }
+        });
+        assert_eq!(*calls.lock().unwrap(), 0);
+        assert_eq!(*doubled.get(), 2);
+        assert_eq!(*doubled.get(), 2);
+        assert_eq!(*calls.lock().unwrap(), 1);
+        atom.set(5);
+        assert_eq!(*calls.lock().unwrap(), 1);
+        assert_eq!(*doubled.get(), 10);
+        assert_eq!(*calls.lock().unwrap(), 2);
+        drop(engine);
+    }
+
+    #[test]
+    fn reactions_depend_on_computed_values() {
+        let engine = Arc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let doubled = Computed::new(engine.clone(), {
+            let atom = atom.clone();
+            move || *atom.get() * 2
+        });
+        let quadrupled = Computed::new(engine.clone(), move || *doubled.get() * 2);
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        engine.react({
+            let sink = sink.clone();
+            move || sink.lock().unwrap().push(*quadrupled.get())
+        });
+        atom.set(2);
+        atom.set(3);
+        assert_eq!(*sink.lock().unwrap(), [4, 8, 12]);
+    }
+
+    #[test]
+    fn writes_from_other_threads_make_it_stale() {
+        let engine = Arc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 0);
+        let doubled = Computed::new(engine.clone(), {
+            let atom = atom.clone();
+            move || *atom.get() * 2
+        });
+        assert_eq!(*doubled.get(), 0);
+        thread::spawn(move || atom.set(21)).join().unwrap();
+        assert_eq!(*doubled.get(), 42);
+        drop(engine);
+    }
+
+    #[test]
+    fn owner_disposes_it() {
+        let engine = Arc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let computed = Arc::new(Mutex::new(None));
+        engine.react({
+            let engine = engine.clone();
+            let atom = atom.clone();
+            let computed = computed.clone();
+            move || {
+                drop(atom.get());
+                let doubled = Computed::new(engine.clone(), || 2);
+                *computed.lock().unwrap() = Some(doubled);
+            }
+        });
+        let first = computed.lock().unwrap().clone().unwrap();
+        assert_eq!(*first.get(), 2);
+        atom.set(2);
+        assert!(first.try_get().is_err());
+        let second = computed.lock().unwrap().clone().unwrap();
+        assert_eq!(*second.get(), 2);
+    }
+}
@@ -0,0 +1,969 @@
+use crate::{
+    sync::{Reaction, Scope},
+    Error,
+};
+use parking_lot::{Mutex, ReentrantMutex, ReentrantMutexGuard};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    mem,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+        Weak,
+    },
+    thread,
+};
+
+/// A thread-safe reactive engine.
+///
+/// Locking works like this: reactions only run while holding `state`, so one
+/// thread runs them at a time. It's reentrant, so reactions can read and write
+/// atoms on their own thread. Nothing else waits for it. Atoms lock their own
+/// values, and a thread that writes one while another thread holds `state`
+/// leaves the reactions it triggered in `pending`, for that thread to run
+/// before it lets go.
+///
+/// The smaller locks inside sources and observers are only held for a moment,
+/// and nothing is called while holding them. The only time two are held at
+/// once is when tracking locks a source's subscribers while holding the
+/// observer's sources.
+pub struct Engine {
+    id: u64,
+    state: ReentrantMutex<RefCell<Option<Update>>>,
+    pending: Mutex<Vec<Write>>,
+    /// Reactions that aren't owned by another reaction or a scope. Sources
+    /// only hold their subscribers weakly, so something has to keep these
+    /// alive.
+    unowned: Mutex<HashMap<ObserverId, Arc<Observer>>>,
+    max_iterations: AtomicU32,
+}
+
+type StateGuard<'a> = ReentrantMutexGuard<'a, RefCell<Option<Update>>>;
+
+/// Identifies a reaction. No two reactions in the same process share an id.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ObserverId(u64);
+
+impl ObserverId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+thread_local! {
+    /// Everything running on this thread, innermost last, whichever engine it
+    /// belongs to.
+    static FRAMES: RefCell<Vec<Frame>> = RefCell::new(Vec::new());
+}
+
+struct Frame {
+    engine: u64,
+    observer: Arc<Observer>,
+    /// Whether reads are currently being tracked. This is false for roots, and
+    /// inside `untrack`.
+    tracking: bool,
+}
+
+#[derive(Default)]
+struct Update {
+    /// Reactions waiting to run, in the order they were queued.
+    queue: VecDeque<Arc<Observer>>,
+    /// Every reaction that has run so far, so their run counts can be reset.
+    ran: Vec<Arc<Observer>>,
+    /// Every atom written so far, in order.
+    writes: Vec<Arc<Source>>,
+}
+
+/// A write that hasn't been handed to an update yet.
+struct Write {
+    source: Arc<Source>,
+    reactions: Vec<Arc<Observer>>,
+}
+
+impl Engine {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how many times a single reaction may run during one update before
+    /// the engine gives up and reports a cycle. The default is 100.
+    pub fn set_max_iterations(&self, max_iterations: u32) {
+        self.max_iterations.store(max_iterations, Ordering::Relaxed);
+    }
+
+    /// Subscribes the current reaction or computed value, if any, to a source.
+    ///
+    /// Reading a source while another engine's reaction is running is an
+    /// error, since that reaction would never hear about changes.
+    pub(crate) fn track(&self, source: &Arc<Source>) -> Result<(), Error> {
+        let observer = FRAMES.with(|frames| {
+            match frames.borrow().last() {
+                Some(frame) if frame.tracking && frame.engine == self.id => {
+                    Ok(Some(frame.observer.clone()))
+                }
+                Some(frame) if frame.tracking => {
+                    Err(Error::WrongEngine {
+                        name: source.describe(),
+                    })
+                }
+                _ => Ok(None),
+            }
+        })?;
+        let observer = match observer {
+            Some(observer) if !observer.is_disposed() => observer,
+            _ => return Ok(()),
+        };
+
+        let mut sources = observer.sources.lock();
+        if sources.iter().any(|s| Arc::ptr_eq(s, source)) {
+            return Ok(());
+        }
+        sources.push(source.clone());
+        source.subscribers.lock().push(Arc::downgrade(&observer));
+        Ok(())
+    }
+
+    /// Whether a reaction or computed value from this engine is running on
+    /// this thread, and tracking what it reads.
+    #[must_use]
+    pub fn is_tracking(&self) -> bool {
+        FRAMES.with(|frames| {
+            frames
+                .borrow()
+                .last()
+                .map_or(false, |frame| frame.tracking && frame.engine == self.id)
+        })
+    }
+
+    /// Defers every reaction until the batch is dropped. Writes from other
+    /// threads are deferred too, and run on this thread when it's dropped.
+    pub fn batch(&self) -> Batch<'_> {
+        let state = self.state.lock();
+        let root = Self::begin_update(&state);
+        Batch {
+            engine: self,
+            root,
+            state: Some(state),
+        }
+    }
+
+    fn begin_update(state: &StateGuard<'_>) -> bool {
+        let mut update = state.borrow_mut();
+        if update.is_some() {
+            return false;
+        }
+        *update = Some(Update::default());
+        true
+    }
+
+    fn end_update(&self, state: &StateGuard<'_>) {
+        if let Err(error) = self.try_end_update(state) {
+            panic!("{}", error);
+        }
+    }
+
+    fn try_end_update(&self, state: &StateGuard<'_>) -> Result<(), Error> {
+        // End the update even if a reaction panics, dropping the rest of the
+        // queue, so the next update starts from a clean slate.
+        let _end = scopeguard::guard((), |()| Self::abort_update(state));
+        while let Some(head) = self.next(state)? {
+            self.run(&head);
+        }
+        Ok(())
+    }
+
+    /// Takes the next reaction to run, or returns an error describing the
+    /// cycle if it has already run too many times. Writes from other threads
+    /// join the queue here.
+    fn next(&self, state: &StateGuard<'_>) -> Result<Option<Arc<Observer>>, Error> {
+        let pending = mem::take(&mut *self.pending.lock());
+        // Reactions that were already queued are dropped once nothing is
+        // borrowed, since that might be the last reference.
+        let mut already_queued = Vec::new();
+        let mut update = state.borrow_mut();
+        let update = update.as_mut().unwrap();
+        for write in pending {
+            update.writes.push(write.source);
+            for observer in write.reactions {
+                if observer.scheduled.swap(true, Ordering::Relaxed) {
+                    already_queued.push(observer);
+                } else {
+                    update.queue.push_back(observer);
+                }
+            }
+        }
+
+        let head = match update.queue.pop_front() {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        head.scheduled.store(false, Ordering::Relaxed);
+
+        let runs = head.runs.fetch_add(1, Ordering::Relaxed) + 1;
+        if runs == 1 {
+            update.ran.push(head.clone());
+        }
+        let max_iterations = self.max_iterations.load(Ordering::Relaxed);
+        if runs > max_iterations {
+            let write_mark = head.write_mark.load(Ordering::Relaxed);
+            let mut names: Vec<String> = Vec::new();
+            for source in &update.writes[write_mark..] {
+                let name = source.describe();
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            return Err(Error::CycleDetected {
+                max_iterations,
+                names,
+            });
+        }
+        head.write_mark
+            .store(update.writes.len(), Ordering::Relaxed);
+        Ok(Some(head))
+    }
+
+    fn abort_update(state: &StateGuard<'_>) {
+        let update = state.borrow_mut().take().unwrap();
+        for observer in update.queue {
+            observer.scheduled.store(false, Ordering::Relaxed);
+        }
+        for observer in update.ran {
+            observer.runs.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Runs `f` inside an update, then runs whatever it queued.
+    fn in_update<T>(&self, f: impl FnOnce() -> T) -> T {
+        let result = {
+            let state = self.state.lock();
+            let root = Self::begin_update(&state);
+            let result = {
+                let _abort = scopeguard::guard_on_unwind((), |()| {
+                    if root {
+                        Self::abort_update(&state);
+                    }
+                });
+                f()
+            };
+            if root {
+                self.end_update(&state);
+            }
+            result
+        };
+        self.flush_pending();
+        result
+    }
+
+    /// Records a write to `source`, and queues everything that depends on it.
+    /// If nothing is running, this runs them right away.
+    pub(crate) fn write(&self, source: &Arc<Source>) {
+        if let Err(error) = self.try_write(source) {
+            panic!("{}", error);
+        }
+    }
+
+    /// Like `write`, but returns an error if running the queue finds a cycle.
+    /// If an update is already in progress, on this thread or another one,
+    /// whoever started it finds out instead.
+    pub(crate) fn try_write(&self, source: &Arc<Source>) -> Result<(), Error> {
+        // Computed values go stale right away, so this thread sees its own
+        // write through them even if the reactions have to wait.
+        let reactions = source.invalidate();
+        self.pending.lock().push(Write {
+            source: source.clone(),
+            reactions,
+        });
+        self.try_flush_pending()
+    }
+
+    fn flush_pending(&self) {
+        if let Err(error) = self.try_flush_pending() {
+            panic!("{}", error);
+        }
+    }
+
+    /// Runs the reactions for pending writes, unless another thread is already
+    /// running reactions, in which case it picks them up instead.
+    fn try_flush_pending(&self) -> Result<(), Error> {
+        loop {
+            {
+                let state = match self.state.try_lock() {
+                    Some(state) => state,
+                    None => return Ok(()),
+                };
+                if !Self::begin_update(&state) {
+                    // An update is in progress further up this thread's stack,
+                    // and it takes the writes before it finishes.
+                    return Ok(());
+                }
+                self.try_end_update(&state)?;
+            }
+            // Another thread may have written something after the last reaction
+            // ran, but before the lock was released.
+            if self.pending.lock().is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn react(self: &Arc<Self>, f: impl FnMut() + Send + 'static) -> Reaction {
+        let observer = Arc::new(Observer::new(Kind::Reaction, Some(Box::new(f))));
+        self.adopt(&observer);
+        self.in_update(|| self.run(&observer));
+        Reaction::new(self.clone(), observer)
+    }
+
+    /// Adds the observer behind a computed value. It starts out stale, and
+    /// `f` only runs when someone reads it.
+    pub(crate) fn add_computed(
+        &self,
+        source: &Arc<Source>,
+        f: impl FnMut() + Send + 'static,
+    ) -> Arc<Observer> {
+        let observer = Arc::new(Observer::new(
+            Kind::Computed(source.clone()),
+            Some(Box::new(f)),
+        ));
+        observer.stale.store(true, Ordering::Relaxed);
+        if self.owner().is_some() {
+            self.adopt(&observer);
+        }
+        observer
+    }
+
+    /// Makes the reaction or computed value that's running on this thread own
+    /// `observer`, so it's disposed when that runs again. Reactions created
+    /// outside of one are kept by the engine instead.
+    fn adopt(&self, observer: &Arc<Observer>) {
+        if let Some(owner) = self.owner() {
+            owner.owned.lock().push(observer.clone());
+        } else {
+            self.unowned.lock().insert(observer.id, observer.clone());
+        }
+    }
+
+    /// The innermost reaction, computed value or root from this engine running
+    /// on this thread.
+    fn owner(&self) -> Option<Arc<Observer>> {
+        FRAMES.with(|frames| {
+            frames.borrow().iter().rev().find_map(|frame| {
+                if frame.engine == self.id {
+                    Some(frame.observer.clone())
+                } else {
+                    None
+                }
+            })
+        })
+    }
+
+    /// Runs `f` inside a new ownership tree that isn't owned by whatever is
+    /// currently running. Reactions and computed values created by `f` live
+    /// until the returned scope is disposed.
+    pub fn create_root<T>(self: &Arc<Self>, f: impl FnOnce() -> T) -> (Scope, T) {
+        let root = Arc::new(Observer::new(Kind::Root, None));
+        let result = self.in_update(|| {
+            let _frame = self.push_frame(&root, false);
+            f()
+        });
+        (Scope::new(self.clone(), root), result)
+    }
+
+    /// Runs `f` without tracking anything it reads. Reactions and computed
+    /// values created inside `f` still track their own reads, and are still
+    /// owned by the current reaction.
+    pub fn untrack<T>(&self, f: impl FnOnce() -> T) -> T {
+        // If another engine is running inside this one, it keeps tracking.
+        let set_tracking = |tracking| {
+            FRAMES.with(|frames| {
+                match frames.borrow_mut().last_mut() {
+                    Some(frame) if frame.engine == self.id => {
+                        mem::replace(&mut frame.tracking, tracking)
+                    }
+                    _ => false,
+                }
+            })
+        };
+        let previous = set_tracking(false);
+        let _restore = scopeguard::guard((), |()| {
+            set_tracking(previous);
+        });
+        f()
+    }
+
+    /// Registers `f` to be called before the currently running reaction runs
+    /// again, or when it is disposed. If nothing is running, `f` is never
+    /// called.
+    pub fn on_cleanup(&self, f: impl FnOnce() + Send + 'static) {
+        if let Some(owner) = self.owner() {
+            owner.cleanups.lock().push(Box::new(f));
+        }
+    }
+
+    /// Pushes a frame onto this thread's stack, and returns a guard that pops
+    /// it again, even if whatever runs in between panics.
+    fn push_frame(&self, observer: &Arc<Observer>, tracking: bool) -> impl Drop {
+        FRAMES.with(|frames| {
+            frames.borrow_mut().push(Frame {
+                engine: self.id,
+                observer: observer.clone(),
+                tracking,
+            });
+        });
+        scopeguard::guard((), |()| {
+            // Dropped outside the borrow, in case it's the last reference.
+            let frame = FRAMES.with(|frames| frames.borrow_mut().pop());
+            drop(frame);
+        })
+    }
+
+    /// Runs a reaction or computed value, replacing whatever dependencies it
+    /// had before with the ones it reads this time.
+    pub(crate) fn run(&self, observer: &Arc<Observer>) {
+        if observer.is_disposed() {
+            return;
+        }
+        observer.reset();
+
+        // Take the closure out while it runs, so the reaction can dispose itself.
+        let f = match observer.f.lock().take() {
+            Some(f) => f,
+            None => return,
+        };
+        observer.running.store(true, Ordering::Relaxed);
+        let mut f = scopeguard::guard(f, |f| {
+            observer.running.store(false, Ordering::Relaxed);
+            if observer.is_disposed() {
+                // Drop whatever it subscribed to or registered on the way out.
+                observer.reset();
+                drop(f);
+            } else {
+                *observer.f.lock() = Some(f);
+            }
+        });
+        let _frame = self.push_frame(observer, true);
+        (&mut *f)();
+    }
+
+    pub(crate) fn dispose(&self, observer: &Arc<Observer>) {
+        let unowned = self.unowned.lock().remove(&observer.id);
+        observer.dispose();
+        drop(unowned);
+    }
+
+    /// Unsubscribes everything from a source that's being dropped, and disposes
+    /// reactions that nothing can trigger anymore.
+    pub(crate) fn remove_source(&self, source: &Arc<Source>) {
+        let subscribers = mem::take(&mut *source.subscribers.lock());
+        for observer in subscribers.iter().filter_map(Weak::upgrade) {
+            observer.sources.lock().retain(|s| !Arc::ptr_eq(s, source));
+            if observer.is_orphan() {
+                self.dispose(&observer);
+            }
+        }
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            state: ReentrantMutex::new(RefCell::new(None)),
+            pending: Mutex::new(Vec::new()),
+            unowned: Mutex::new(HashMap::new()),
+            max_iterations: AtomicU32::new(100),
+        }
+    }
+}
+
+/// The part of an atom or computed value that reactions subscribe to.
+pub(crate) struct Source {
+    /// Held weakly, so that a reaction that reads an atom doesn't keep itself
+    /// alive through it.
+    subscribers: Mutex<Vec<Weak<Observer>>>,
+    name: Mutex<Option<Cow<'static, str>>>,
+    type_name: &'static str,
+}
+
+impl Source {
+    pub fn new(type_name: &'static str) -> Self {
+        Self {
+            subscribers: Mutex::new(Vec::new()),
+            name: Mutex::new(None),
+            type_name,
+        }
+    }
+
+    pub fn set_name(&self, name: Cow<'static, str>) {
+        *self.name.lock() = Some(name);
+    }
+
+    /// Describes the source for error messages.
+    pub fn describe(&self) -> String {
+        match &*self.name.lock() {
+            Some(name) => format!("`{}`", name),
+            None => format!("an unnamed `{}`", self.type_name),
+        }
+    }
+
+    /// Marks every computed value that depends on this, directly or not, as
+    /// stale, and returns every reaction that depends on any of them.
+    fn invalidate(self: &Arc<Self>) -> Vec<Arc<Observer>> {
+        let mut reactions = Vec::new();
+        let mut visited = Vec::new();
+        let mut changed = vec![self.clone()];
+        while let Some(source) = changed.pop() {
+            let subscribers: Vec<_> = source
+                .subscribers
+                .lock()
+                .iter()
+                .filter_map(Weak::upgrade)
+                .collect();
+            for observer in subscribers {
+                match &observer.kind {
+                    Kind::Reaction => reactions.push(observer),
+                    Kind::Computed(computed) => {
+                        observer.stale.store(true, Ordering::Relaxed);
+                        // Computed values can share dependencies, so don't
+                        // walk the same one twice.
+                        if !visited.iter().any(|s| Arc::ptr_eq(s, computed)) {
+                            visited.push(computed.clone());
+                            changed.push(computed.clone());
+                        }
+                    }
+                    Kind::Root => {}
+                }
+            }
+        }
+        reactions
+    }
+}
+
+/// What kind of thing an observer is.
+pub(crate) enum Kind {
+    Reaction,
+    /// A computed value, and the source that its readers subscribe to.
+    Computed(Arc<Source>),
+    /// Only owns things, and never runs or tracks anything.
+    Root,
+}
+
+pub(crate) struct Observer {
+    id: ObserverId,
+    kind: Kind,
+    f: Mutex<Option<Box<dyn FnMut() + Send>>>,
+    /// Every atom read during the last run.
+    sources: Mutex<Vec<Arc<Source>>>,
+    /// Reactions and computed values created during the last run.
+    owned: Mutex<Vec<Arc<Observer>>>,
+    /// Callbacks registered with `on_cleanup` during the last run.
+    cleanups: Mutex<Vec<Box<dyn FnOnce() + Send>>>,
+    /// Whether a computed value needs to run again before it's read.
+    stale: AtomicBool,
+    running: AtomicBool,
+    /// Whether this is already queued in the current update.
+    scheduled: AtomicBool,
+    /// How many times this has run during the current update.
+    runs: AtomicU32,
+    /// The length of the current update's write log when this last ran.
+    write_mark: AtomicUsize,
+    disposed: AtomicBool,
+}
+
+impl Observer {
+    fn new(kind: Kind, f: Option<Box<dyn FnMut() + Send>>) -> Self {
+        Self {
+            id: ObserverId::next(),
+            kind,
+            f: Mutex::new(f),
+            sources: Mutex::new(Vec::new()),
+            owned: Mutex::new(Vec::new()),
+            cleanups: Mutex::new(Vec::new()),
+            stale: AtomicBool::new(false),
+            running: AtomicBool::new(false),
+            scheduled: AtomicBool::new(false),
+            runs: AtomicU32::new(0),
+            write_mark: AtomicUsize::new(0),
+            disposed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn id(&self) -> ObserverId {
+        self.id
+    }
+
+    pub fn is_disposed(&self) -> bool {
+        self.disposed.load(Ordering::Relaxed)
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.stale.load(Ordering::Relaxed)
+    }
+
+    /// Clears a computed value's stale flag, and returns what it was.
+    pub fn take_stale(&self) -> bool {
+        self.stale.swap(false, Ordering::Relaxed)
+    }
+
+    /// Whether this is a reaction that nothing can ever trigger again.
+    fn is_orphan(&self) -> bool {
+        match self.kind {
+            Kind::Reaction => {
+                self.sources.lock().is_empty()
+                    && self.owned.lock().is_empty()
+                    && !self.running.load(Ordering::Relaxed)
+            }
+            Kind::Computed(_) | Kind::Root => false,
+        }
+    }
+
+    /// Gets the observer ready to run again: unsubscribes it, disposes what it
+    /// owns, and calls its cleanups.
+    fn reset(self: &Arc<Self>) {
+        let weak = Arc::downgrade(self);
+        let sources = mem::take(&mut *self.sources.lock());
+        for source in &sources {
+            source
+                .subscribers
+                .lock()
+                .retain(|s| !Weak::ptr_eq(s, &weak) && s.strong_count() > 0);
+        }
+        // Take them out first, since disposing them or calling them runs
+        // arbitrary code, which might create or register more.
+        let owned = mem::take(&mut *self.owned.lock());
+        for observer in &owned {
+            observer.dispose();
+        }
+        let cleanups = mem::take(&mut *self.cleanups.lock());
+        for cleanup in cleanups {
+            cleanup();
+        }
+    }
+
+    /// Disposes the observer and everything it owns. If it's running right
+    /// now, `Engine::run` drops its closure once it returns.
+    pub fn dispose(self: &Arc<Self>) {
+        if self.disposed.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let f = self.f.lock().take();
+        self.reset();
+        drop(f);
+    }
+}
+
+#[must_use]
+pub struct Batch<'a> {
+    engine: &'a Engine,
+    root: bool,
+    state: Option<StateGuard<'a>>,
+}
+
+impl Drop for Batch<'_> {
+    fn drop(&mut self) {
+        let state = self.state.take().unwrap();
+        if !self.root {
+            return;
+        }
+        // If the batch is being dropped by a panic, don't run anything.
+        if thread::panicking() {
+            Engine::abort_update(&state);
+            return;
+        }
+        self.engine.end_update(&state);
+        drop(state);
+        self.engine.flush_pending();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        sync::{Atom, Engine},
+        Error,
+    };
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    #[test]
+    fn engine_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Engine>();
+        assert_send_sync::<Atom<i32>>();
+    }
+
+    #[test]
+    fn react_simple() {
+        let engine = Arc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || sink.lock().unwrap().push(*atom.get())
+        });
+        atom.set(2);
+        atom.set(3);
+        assert_eq!(*sink.lock().unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn batch_defers_and_dedups() {
+        let engine = Arc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 0);
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || sink.lock().unwrap().push(*atom.get())
+        });
+
+        let batch = engine.batch();
+        atom.set(1);
+        atom.set(2);
+        assert_eq!(*sink.lock().unwrap(), [0]);
+        drop(batch);
+        assert_eq!(*sink.lock().unwrap(), [0, 2]);
+    }
+
+    #[test]
+    fn writes_from_many_threads() {
+        let engine = Arc::new(Engine::new());
+        let count = Atom::new(engine.clone(), 0);
+        let doubled = Atom::new(engine.clone(), 0);
+        engine.react({
+            let count = count.clone();
+            let doubled = doubled.clone();
+            move || doubled.set(count.get_cloned() * 2)
+        });
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let count = count.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        count.update(|n| *n += 1);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(*count.sample(), 400);
+        assert_eq!(*doubled.sample(), 800);
+    }
+
+    #[test]
+    fn writing_while_holding_another_atom_does_not_deadlock() {
+        let engine = Arc::new(Engine::new());
+        let a = Atom::new(engine.clone(), 0);
+        let b = Atom::new(engine.clone(), 0);
+        // Runs on whichever thread writes `b`, and reads `a`.
+        engine.react({
+            let a = a.clone();
+            let b = b.clone();
+            move || {
+                a.with(|_| ());
+                b.with(|_| ());
+            }
+        });
+
+        // One thread writes `b` while it holds `a`, and the other writes `b`
+        // on its own, which runs the reaction and reads `a`.
+        let holding = thread::spawn({
+            let b = b.clone();
+            move || {
+                for _ in 0..1000 {
+                    a.with(|_| b.update(|b| *b += 1));
+                }
+            }
+        });
+        let reading = thread::spawn({
+            let b = b.clone();
+            move || {
+                for _ in 0..1000 {
+                    b.update(|b| *b += 1);
+                }
+            }
+        });
+        holding.join().unwrap();
+        reading.join().unwrap();
+        assert_eq!(*b.sample(), 2000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Atoms written since it last ran: `pong`, `ping`")]
+    fn cycle_is_reported() {
+        let engine = Arc::new(Engine::new());
+        let ping = Atom::new(engine.clone(), 0).named("ping");
+        let pong = Atom::new(engine.clone(), 0).named("pong");
+        engine.set_max_iterations(10);
+        engine.react({
+            let ping = ping.clone();
+            let pong = pong.clone();
+            move || pong.set(ping.get_cloned() + 1)
+        });
+        engine.react(move || ping.set(pong.get_cloned() + 1));
+    }
+
+    #[test]
+    fn try_set_reports_cycles() {
+        let engine = Arc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 0).named("count");
+        engine.set_max_iterations(3);
+        engine.react({
+            let atom = atom.clone();
+            move || {
+                let n = atom.get_cloned();
+                if n > 0 {
+                    drop(atom.try_set(n + 1));
+                }
+            }
+        });
+        assert_eq!(
+            atom.try_set(1),
+            Err(Error::CycleDetected {
+                max_iterations: 3,
+                names: vec!["`count`".into()],
+            }),
+        );
+
+        // The engine recovers afterwards.
+        let other = Atom::new(engine.clone(), 1);
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        engine.react({
+            let sink = sink.clone();
+            move || sink.lock().unwrap().push(other.get_cloned())
+        });
+        atom.set(0);
+        assert_eq!(*sink.lock().unwrap(), [1]);
+    }
+
+    #[test]
+    fn on_cleanup() {
+        let engine = Arc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        let reaction = engine.react({
+            let engine = engine.clone();
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || {
+                let value = atom.get_cloned();
+                sink.lock().unwrap().push(format!("run {}", value));
+                engine.on_cleanup({
+                    let sink = sink.clone();
+                    move || sink.lock().unwrap().push(format!("clean up {}", value))
+                });
+            }
+        });
+        atom.set(2);
+        reaction.dispose();
+        atom.set(3);
+        assert_eq!(*sink.lock().unwrap(), [
+            "run 1",
+            "clean up 1",
+            "run 2",
+            "clean up 2",
+        ]);
+    }
+
+    #[test]
+    fn untrack() {
+        let engine = Arc::new(Engine::new());
+        let tracked = Atom::new(engine.clone(), 1);
+        let untracked = Atom::new(engine.clone(), 10);
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        engine.react({
+            let engine = engine.clone();
+            let tracked = tracked.clone();
+            let untracked = untracked.clone();
+            let sink = sink.clone();
+            move || {
+                let a = tracked.get_cloned();
+                let b = engine.untrack(|| untracked.get_cloned());
+                sink.lock().unwrap().push(a + b);
+            }
+        });
+        untracked.set(20);
+        assert_eq!(*sink.lock().unwrap(), [11]);
+        tracked.set(2);
+        assert_eq!(*sink.lock().unwrap(), [11, 22]);
+    }
+
+    #[test]
+    fn guard_does_not_block_other_threads() {
+        let engine = Arc::new(Engine::new());
+        let held = Atom::new(engine.clone(), 0);
+        let written = Atom::new(engine.clone(), 0);
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        engine.react({
+            let written = written.clone();
+            let sink = sink.clone();
+            move || sink.lock().unwrap().push(written.get_cloned())
+        });
+
+        // The other thread would wait forever if the guard locked the engine.
+        let guard = held.get();
+        thread::spawn(move || written.set(1)).join().unwrap();
+        assert_eq!(*guard, 0);
+        assert_eq!(*sink.lock().unwrap(), [0, 1]);
+    }
+
+    #[test]
+    fn writes_during_another_threads_batch_run_when_it_ends() {
+        let engine = Arc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 0);
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || {
+                sink.lock()
+                    .unwrap()
+                    .push((thread::current().id(), atom.get_cloned()))
+            }
+        });
+
+        let batch = engine.batch();
+        thread::spawn({
+            let atom = atom.clone();
+            move || atom.set(1)
+        })
+        .join()
+        .unwrap();
+        assert_eq!(*atom.sample(), 1);
+        assert_eq!(sink.lock().unwrap().len(), 1);
+        drop(batch);
+        let this_thread = thread::current().id();
+        assert_eq!(*sink.lock().unwrap(), [(this_thread, 0), (this_thread, 1)]);
+    }
+
+    #[test]
+    fn reading_another_engines_atom_is_an_error() {
+        let engine = Arc::new(Engine::new());
+        let other = Arc::new(Engine::new());
+        let atom = Atom::new(other.clone(), 0).named("other");
+        let result = Arc::new(Mutex::new(None));
+        engine.react({
+            let result = result.clone();
+            move || *result.lock().unwrap() = Some(atom.try_get().map(|_| ()))
+        });
+        assert_eq!(
+            *result.lock().unwrap(),
+            Some(Err(Error::WrongEngine {
+                name: "`other`".into(),
+            })),
+        );
+        drop(other);
+    }
+}
@@ -0,0 +1,106 @@
+//! A thread-safe engine, for when atoms need to be shared between threads.
+//!
+//! This mirrors [`cope::instance`](crate::instance), but everything is `Send`
+//! and `Sync`, and the free functions here use one engine for the whole
+//! process instead of one per thread. Reactions run on whichever thread wrote
+//! the atom that triggered them, one update at a time. If another thread is
+//! already running reactions, a write leaves its reactions for that thread
+//! instead of waiting for it.
+//!
+//! Atom guards only lock the atom's value, so a guard held on one thread only
+//! makes writers of that same atom wait. Like any other locks, two threads
+//! that each hold a guard and wait for the other's can deadlock, and so can a
+//! thread that holds a guard while it starts a reaction, a batch or a root.
+//! Keep guards short, or use [`Atom::with`] and [`Atom::update`].
+//!
+//! Schedulers and panic policies aren't supported here yet. A reaction that
+//! panics ends the update, and the panic reaches whoever triggered it.
+
+pub use self::{
+    atom::{Atom, AtomMut, AtomRef},
+    computed::Computed,
+    engine::{Batch, Engine, ObserverId},
+    reaction::{Reaction, ReactionGuard},
+    scope::Scope,
+};
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+mod atom;
+mod computed;
+mod engine;
+mod reaction;
+mod scope;
+
+/// Returns the engine that the free functions in this module use. It's
+/// created the first time it's needed, and lives as long as the process.
+#[must_use]
+pub fn engine() -> Arc<Engine> {
+    global().clone()
+}
+
+fn global() -> &'static Arc<Engine> {
+    static ENGINE: Mutex<Option<&'static Arc<Engine>>> = parking_lot::const_mutex(None);
+    ENGINE
+        .lock()
+        .get_or_insert_with(|| Box::leak(Box::new(Arc::new(Engine::new()))))
+}
+
+pub fn batch() -> Batch<'static> {
+    global().batch()
+}
+
+pub fn react(f: impl FnMut() + Send + 'static) -> Reaction {
+    global().react(f)
+}
+
+pub fn untrack<T>(f: impl FnOnce() -> T) -> T {
+    global().untrack(f)
+}
+
+pub fn on_cleanup(f: impl FnOnce() + Send + 'static) {
+    global().on_cleanup(f)
+}
+
+#[must_use]
+pub fn is_tracking() -> bool {
+    global().is_tracking()
+}
+
+pub fn create_root<T>(f: impl FnOnce() -> T) -> (Scope, T) {
+    global().create_root(f)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sync::{self, Atom};
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    #[test]
+    fn free_functions_share_one_engine_between_threads() {
+        let atom = Atom::new(sync::engine(), 1);
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        let reaction = sync::react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || sink.lock().unwrap().push(*atom.get())
+        });
+        thread::spawn({
+            let atom = atom.clone();
+            move || {
+                let _batch = sync::batch();
+                atom.set(2);
+                atom.set(3);
+            }
+        })
+        .join()
+        .unwrap();
+        reaction.dispose();
+        atom.set(4);
+        assert_eq!(*sink.lock().unwrap(), [1, 3]);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::sync::{
+    engine::{Engine, Observer},
+    ObserverId,
+};
+use std::sync::Arc;
+
+/// A handle to a reaction created by [`Engine::react`].
+///
+/// Dropping the handle leaves the reaction running. Call [`dispose`] to stop
+/// it, or convert it with [`into_guard`] to stop it when the guard is dropped.
+///
+/// The handle keeps the engine alive, but atoms and computed values don't, and
+/// atoms only hold the reactions that read them weakly. So a reaction that
+/// captures them doesn't keep itself alive. Once every handle to the engine is
+/// dropped, the engine is freed along with every reaction and whatever their
+/// closures captured.
+///
+/// [`Engine::react`]: crate::sync::Engine::react
+/// [`dispose`]: Reaction::dispose
+/// [`into_guard`]: Reaction::into_guard
+#[derive(Clone)]
+pub struct Reaction {
+    engine: Arc<Engine>,
+    observer: Arc<Observer>,
+}
+
+impl Reaction {
+    pub(crate) fn new(engine: Arc<Engine>, observer: Arc<Observer>) -> Self {
+        Self { engine, observer }
+    }
+
+    #[must_use]
+    pub fn id(&self) -> ObserverId {
+        self.observer.id()
+    }
+
+    #[must_use]
+    pub fn is_disposed(&self) -> bool {
+        self.observer.is_disposed()
+    }
+
+    /// Unsubscribes the reaction from every atom it depends on and drops its
+    /// closure. It will never run again.
+    pub fn dispose(&self) {
+        self.engine.dispose(&self.observer);
+    }
+
+    pub fn into_guard(self) -> ReactionGuard {
+        ReactionGuard { reaction: self }
+    }
+}
+
+/// Disposes a reaction when dropped.
+#[must_use]
+#[allow(clippy::module_name_repetitions)]
+pub struct ReactionGuard {
+    reaction: Reaction,
+}
+
+impl Drop for ReactionGuard {
+    fn drop(&mut self) {
+        self.reaction.dispose();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sync::{Atom, Engine};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn dispose() {
+        let engine = Arc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        let reaction = engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || sink.lock().unwrap().push(*atom.get())
+        });
+        atom.set(2);
+        reaction.dispose();
+        assert!(reaction.is_disposed());
+        atom.set(3);
+        assert_eq!(*sink.lock().unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn guard_disposes_on_drop() {
+        let engine = Arc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        let reaction = engine.react({
+            let atom = atom.clone();
+            let sink = sink.clone();
+            move || sink.lock().unwrap().push(*atom.get())
+        });
+        let other = engine.react(|| ());
+        assert_ne!(reaction.id(), other.id());
+
+        let guard = reaction.clone().into_guard();
+        atom.set(2);
+        drop(guard);
+        assert!(reaction.is_disposed());
+        atom.set(3);
+        assert_eq!(*sink.lock().unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn dropping_every_handle_frees_captured_atoms_and_engine() {
+        let engine = Arc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let captured = Arc::new(());
+        let reaction = engine.react({
+            let atom = atom.clone();
+            let captured = captured.clone();
+            move || {
+                let _ = (*atom.get(), &captured);
+            }
+        });
+        atom.set(2);
+        assert_eq!(Arc::strong_count(&captured), 2);
+
+        let weak = Arc::downgrade(&engine);
+        drop((engine, reaction));
+        assert!(weak.upgrade().is_none());
+        assert_eq!(Arc::strong_count(&captured), 1);
+        // The atom still works on its own.
+        atom.set(3);
+        assert_eq!(*atom.sample(), 3);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::sync::engine::{Engine, Observer};
+use std::{mem, sync::Arc};
+
+/// The root of an ownership tree, created by [`Engine::create_root`].
+///
+/// Every reaction and computed value created inside the root is owned by it,
+/// either directly or through the reaction that created it. Dropping the scope
+/// disposes all of them. Call [`leak`] to keep them alive forever instead.
+///
+/// [`Engine::create_root`]: crate::sync::Engine::create_root
+/// [`leak`]: Scope::leak
+#[must_use]
+pub struct Scope {
+    engine: Arc<Engine>,
+    root: Arc<Observer>,
+}
+
+impl Scope {
+    pub(crate) fn new(engine: Arc<Engine>, root: Arc<Observer>) -> Self {
+        Self { engine, root }
+    }
+
+    pub fn dispose(self) {
+        drop(self);
+    }
+
+    pub fn leak(self) {
+        mem::forget(self);
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        self.engine.dispose(&self.root);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sync::{Atom, Engine};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn dropping_root_disposes_reactions() {
+        let engine = Arc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        let (scope, reaction) = engine.create_root(|| {
+            engine.react({
+                let atom = atom.clone();
+                let sink = sink.clone();
+                move || sink.lock().unwrap().push(*atom.get())
+            })
+        });
+        atom.set(2);
+        drop(scope);
+        assert!(reaction.is_disposed());
+        atom.set(3);
+        assert_eq!(*sink.lock().unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn leaked_root_keeps_reactions() {
+        let engine = Arc::new(Engine::new());
+        let atom = Atom::new(engine.clone(), 1);
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        let (scope, ()) = engine.create_root(|| {
+            engine.react({
+                let atom = atom.clone();
+                let sink = sink.clone();
+                move || sink.lock().unwrap().push(*atom.get())
+            });
+        });
+        scope.leak();
+        atom.set(2);
+        assert_eq!(*sink.lock().unwrap(), [1, 2]);
+    }
+}
@@ -3,18 +3,17 @@
 // #![warn(clippy::cargo)]
 #![cfg_attr(feature = "strict", deny(warnings))]
 
-use crate::reactive::{react, Atom};
-use std::io;
-
-mod reactive;
+use cope::sync::{Atom, Engine};
+use std::{io, sync::Arc};
 
 fn main() {
-    let count = Atom::new(0);
+    let engine = Arc::new(Engine::new());
+    let count = Atom::new(engine.clone(), 0);
 
-    react({
+    engine.react({
         let count = count.clone();
         move || {
-            println!("{}", count.get());
+            println!("{}", *count.get());
         }
     });
 
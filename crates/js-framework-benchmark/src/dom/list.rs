@@ -1,5 +1,7 @@
-use crate::reactive::{ListMutation, TrackingVec};
-use cope::singleton::react;
+use cope::{
+    collections::{ListMutation, ReactiveVec},
+    singleton::{create_root, react, Scope},
+};
 use cope_dom::elements::ElementBuilder;
 use wasm_bindgen::UnwrapThrowExt;
 use web_sys::Element;
@@ -23,7 +25,7 @@ impl<E: AsRef<Element>> ElementBuilderChildren for ElementBuilder<E> {
     }
 }
 
-pub fn map_children<T, F>(xs: TrackingVec<T>, f: F) -> MapChildren<T, F>
+pub fn map_children<T, F>(xs: ReactiveVec<T>, f: F) -> MapChildren<T, F>
 where
     F: Fn(&T) -> ElementBuilder<Element> + 'static,
 {
@@ -31,7 +33,7 @@ where
 }
 
 pub struct MapChildren<T, F> {
-    xs: TrackingVec<T>,
+    xs: ReactiveVec<T>,
     f: F,
 }
 
@@ -42,37 +44,43 @@ where
 {
     fn begin(self, parent: Element) {
         let Self { xs, f } = self;
+        let changes = xs.subscribe();
 
-        // Cache the list of children to avoid the slow call to `NodeList#item`.
-        let mut children: Vec<Element> = Vec::new();
+        // Cache the list of children to avoid the slow call to `NodeList#item`. Each
+        // child is built in its own root, so its reactions survive this reaction
+        // re-running, and die when the child is removed.
+        let mut children: Vec<Option<(Element, Scope)>> = Vec::new();
 
         react(move || {
-            // Re-run whenever `xs` changes
-            xs.get(0);
-
-            for mutation in xs.mutations.borrow_mut().drain(..) {
-                // NOTE: This is an incomplete implementation but good enough for proof of
-                // concept.
+            // Inserted items are placeholders until every change has been applied,
+            // at which point their indices match `xs`.
+            for mutation in changes.take() {
                 match mutation {
-                    ListMutation::Insert(index) => {
-                        let item = xs.get(index).unwrap();
-                        let node = f(&item).build();
-
-                        let reference = children.get(index);
-                        parent
-                            .insert_before(&node, reference.map(<_>::as_ref))
-                            .unwrap_throw();
-
-                        children.insert(index, node);
-                    }
+                    ListMutation::Insert(index) => children.insert(index, None),
                     ListMutation::Remove(index) => {
-                        let node = &children[index];
-                        node.remove();
-
-                        children.remove(index);
+                        if let Some((node, scope)) = children.remove(index) {
+                            node.remove();
+                            scope.dispose();
+                        }
                     }
                 }
             }
+
+            // Back to front, so the next sibling is always built already.
+            let items = xs.sample();
+            for index in (0..children.len()).rev() {
+                if children[index].is_some() {
+                    continue;
+                }
+                let (scope, node) = create_root(|| f(&items[index]).build());
+                let reference = children
+                    .get(index + 1)
+                    .map(|child| &child.as_ref().unwrap().0);
+                parent
+                    .insert_before(&node, reference.map(<_>::as_ref))
+                    .unwrap_throw();
+                children[index] = Some((node, scope));
+            }
         });
     }
 }
@@ -1,4 +1,4 @@
-use cope::singleton::react;
+use cope::singleton::{react, Reaction};
 use cope_dom::elements::ElementBuilder;
 use std::cell::Cell;
 use web_sys::Element;
@@ -15,7 +15,11 @@ impl<E: AsRef<Element>> ElementBuilderClass for ElementBuilder<E> {
     }
 }
 
-pub fn toggle_class(element: Element, class: &'static str, f: impl Fn() -> bool + 'static) {
+pub fn toggle_class(
+    element: Element,
+    class: &'static str,
+    f: impl Fn() -> bool + 'static,
+) -> Reaction {
     let previous = Cell::new(false);
 
     react(move || {
@@ -31,5 +35,5 @@ pub fn toggle_class(element: Element, class: &'static str, f: impl Fn() -> bool
             }
             _ => {}
         }
-    });
+    })
 }
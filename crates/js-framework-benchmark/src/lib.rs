@@ -4,14 +4,14 @@
 #![allow(clippy::missing_errors_doc)]
 #![cfg_attr(feature = "strict", deny(warnings))]
 
-use crate::{
-    dom::{
-        list::{map_children, ElementBuilderChildren},
-        misc::toggle_class,
-    },
-    reactive::TrackingVec,
+use crate::dom::{
+    list::{map_children, ElementBuilderChildren},
+    misc::toggle_class,
+};
+use cope::{
+    collections::ReactiveVec,
+    singleton::{self, batch, react, Atom, Selector},
 };
-use cope::singleton::{react, Atom};
 use cope_dom::elements::{a, button, div, h1, span, table, tbody, td, tr, ElementBuilder};
 use js_sys::Math;
 use std::{cell::Cell, rc::Rc};
@@ -20,16 +20,24 @@ use web_sys::{window, Element, Event};
 use wee_alloc::WeeAlloc;
 
 mod dom;
-mod reactive;
 
 #[global_allocator]
 static ALLOC: WeeAlloc<'_> = WeeAlloc::INIT;
 
-#[derive(Default)]
 struct State {
     next_id: Cell<usize>,
-    data: TrackingVec<Rc<Item>>,
-    selected_id: Atom<usize>,
+    data: ReactiveVec<Rc<Item>>,
+    selected_id: Selector<usize>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            next_id: Cell::default(),
+            data: ReactiveVec::new(singleton::engine()),
+            selected_id: Selector::default(),
+        }
+    }
 }
 
 struct Item {
@@ -53,7 +61,7 @@ fn app(state: &Rc<State>) -> ElementBuilder<Element> {
     let handle_select = {
         let selected_id = state.selected_id.clone();
         move |item_id: usize| {
-            selected_id.set(item_id);
+            selected_id.select(item_id);
         }
     };
 
@@ -103,6 +111,7 @@ fn jumbotron(state: Rc<State>) -> ElementBuilder<Element> {
     let run = {
         let state = state.clone();
         move || {
+            let _batch = batch();
             state.data.clear();
             append_rows(&state, 1000);
         }
@@ -111,6 +120,7 @@ fn jumbotron(state: Rc<State>) -> ElementBuilder<Element> {
     let runlots = {
         let state = state.clone();
         move || {
+            let _batch = batch();
             state.data.clear();
             append_rows(&state, 10000);
         }
@@ -126,6 +136,7 @@ fn jumbotron(state: Rc<State>) -> ElementBuilder<Element> {
     let update = {
         let state = state.clone();
         move || {
+            let _batch = batch();
             for item in state.data.as_slice().iter().step_by(10) {
                 *item.label.get_mut() += " !!!";
             }
@@ -193,7 +204,7 @@ fn row(state: &Rc<State>, item: &Rc<Item>) -> ElementBuilder<Element> {
     toggle_class(tr.clone(), "danger", {
         let state = state.clone();
         let item_id = item.id;
-        move || *state.selected_id.get() == item_id
+        move || state.selected_id.is(&item_id)
     });
 
     let id_cell = tr.first_child().unwrap_throw();
@@ -212,9 +223,7 @@ fn row(state: &Rc<State>, item: &Rc<Item>) -> ElementBuilder<Element> {
 }
 
 fn append_rows(state: &State, count: usize) {
-    let data = state.data.batch();
-    data.reserve(count);
-    for _ in 0..count {
+    state.data.extend((0..count).map(|_| {
         state.next_id.set(state.next_id.get() + 1);
         let label = format!(
             "{} {} {}",
@@ -222,11 +231,11 @@ fn append_rows(state: &State, count: usize) {
             random_choice(COLORS),
             random_choice(NOUNS),
         );
-        data.push(Rc::new(Item {
+        Rc::new(Item {
             id: state.next_id.get(),
             label: Atom::new(label),
-        }));
-    }
+        })
+    }));
 }
 
 fn random_choice<'a>(xs: &[&'a str]) -> &'a str {
@@ -1,3 +0,0 @@
-pub use self::tracking_vec::{ListMutation, TrackingVec};
-
-mod tracking_vec;